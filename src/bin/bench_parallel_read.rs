@@ -0,0 +1,61 @@
+#[cfg(target_os = "linux")]
+fn main() {
+    use fiox::{ParallelSequentialReader, SequentialReader, StripeConfig};
+    use std::time::Instant;
+
+    let args = std::env::args().collect::<Vec<String>>();
+    assert!(
+        args.len() > 1,
+        "usage: bench_parallel_read FILE [THREADS] [STRIPE_MB]"
+    );
+    let fpath = args[1].trim();
+    let num_threads = args.get(2).map_or(4, |v| v.parse::<usize>().unwrap());
+    let stripe_mb = args.get(3).map_or(64, |v| v.parse::<u64>().unwrap());
+
+    let mut buf = vec![0u8; 1024 * 1024];
+
+    let now = Instant::now();
+    let mut reader = SequentialReader::new(fpath, 0, 1024 * 1024, 8, None).unwrap();
+    let mut total = 0;
+    loop {
+        let n = reader.read2buf(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        total += n;
+    }
+    report("SequentialReader", total, now);
+
+    let now = Instant::now();
+    let stripe = StripeConfig::new(stripe_mb * 1024 * 1024, num_threads);
+    let mut reader = ParallelSequentialReader::new(fpath, 0, None, stripe, 1024 * 1024, 8).unwrap();
+    let mut total = 0;
+    loop {
+        let n = reader.read2buf(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        total += n;
+    }
+    report(
+        &format!("ParallelSequentialReader x{}", num_threads),
+        total,
+        now,
+    );
+
+    fn report(name: &str, total: usize, now: Instant) {
+        let secs = now.elapsed().as_secs_f64();
+        println!(
+            "{}: {} bytes in {:.3}s, {:.1} MiB/s",
+            name,
+            total,
+            secs,
+            total as f64 / (1024.0 * 1024.0) / secs
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("bench_parallel_read needs linux");
+}
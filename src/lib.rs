@@ -11,12 +11,61 @@ pub use windows::sequential_writer::SequentialWriter;
 
 
 #[cfg(target_os = "linux")]
-pub use linux::sequential_reader::SequentialReader;
+pub use linux::sequential_reader::{FileChange, FileChanged, Lines, ReaderConfig, SequentialReader};
 
 #[cfg(target_os = "linux")]
-pub use linux::sequential_writer::SequentialWriter;
+pub use linux::sequential_writer::{Durability, Preallocation, SequentialWriter, WriterConfig};
+
+#[cfg(target_os = "linux")]
+pub use linux::buffer::{AllocOptions, Buffer, HugePages};
+
+#[cfg(target_os = "linux")]
+pub use linux::buffer_pool::{BufferPool, BufferPoolOptions};
+
+#[cfg(target_os = "linux")]
+pub use linux::io_context::IoContext;
+
+#[cfg(target_os = "linux")]
+pub use linux::io_service::IoService;
+
+#[cfg(target_os = "linux")]
+pub use linux::lock::{LockMode, Locked};
+
+#[cfg(target_os = "linux")]
+pub use linux::random_reader::RandomReader;
+
+#[cfg(target_os = "linux")]
+pub use linux::random_writer::RandomWriter;
+
+#[cfg(target_os = "linux")]
+pub use linux::multi_file_reader::{FileRange, MultiFileReader, Origin};
+
+#[cfg(target_os = "linux")]
+pub use linux::parallel_reader::{ParallelSequentialReader, StripeConfig};
+
+#[cfg(target_os = "linux")]
+pub use linux::parallel_writer::ParallelSequentialWriter;
+
+#[cfg(target_os = "linux")]
+pub use linux::frame::{FrameFormat, FrameReader, FrameWriter, LengthPrefix};
+
+#[cfg(target_os = "linux")]
+pub use linux::par_chunks::{Chunk, ChunkOptions, ParChunks, ParMap, par_chunks};
+
+#[cfg(target_os = "linux")]
+pub use linux::split::{Records, split_ranges};
+
+#[cfg(target_os = "linux")]
+pub use linux::reverse_reader::{ReverseLines, ReverseReader};
+
+#[cfg(target_os = "linux")]
+pub use linux::direct_file::{DirectFile, OpHandle, OpOutput};
+
+#[cfg(all(target_os = "linux", any(feature = "tokio", feature = "futures")))]
+pub use linux::async_io::{AsyncSequentialReader, AsyncSequentialWriter};
 
 #[cfg(test)]
+#[allow(clippy::println_empty_string)]
 mod test {
     use std::{
         fs,
@@ -66,4 +115,1630 @@ mod test {
                 .unwrap();
         }
     }
+
+    #[cfg(target_os = "linux")]
+    fn read_to_end(reader: &mut SequentialReader) -> anyhow::Result<Vec<u8>> {
+        let mut out = vec![];
+        let mut buf = vec![0_u8; 10000];
+        loop {
+            let n = reader.read2buf(&mut buf)?;
+            if n == 0 {
+                return Ok(out);
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn io_kind(err: &anyhow::Error) -> Option<std::io::ErrorKind> {
+        err.chain()
+            .find_map(|e| e.downcast_ref::<std::io::Error>())
+            .map(|e| e.kind())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn io_errno(err: &anyhow::Error) -> Option<i32> {
+        err.chain()
+            .find_map(|e| e.downcast_ref::<std::io::Error>())
+            .and_then(|e| e.raw_os_error())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fault_reader_eio() {
+        use crate::linux::fault::FaultPlan;
+
+        let mut reader =
+            SequentialReader::new("test_data/test_data.txt", 0, 4096, 2, None).unwrap();
+        reader.set_fault_plan(FaultPlan::new().fail_nth(5, libc::EIO));
+        let err = read_to_end(&mut reader).unwrap_err();
+        assert_eq!(io_errno(&err), Some(libc::EIO));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fault_reader_short_and_reordered() {
+        use crate::linux::fault::FaultPlan;
+        use std::time::Duration;
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let plan = FaultPlan::new()
+            .short_nth(0, 100)
+            .short_nth(3, 4095)
+            .short_nth(4, 1)
+            .delay_nth(7, Duration::from_millis(5))
+            .reorder();
+        let mut reader =
+            SequentialReader::new("test_data/test_data.txt", 0, 4096, 4, None).unwrap();
+        reader.set_fault_plan(plan);
+        assert_eq!(read_to_end(&mut reader).unwrap(), expected);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fault_writer_enospc() {
+        use crate::linux::fault::FaultPlan;
+
+        let fpath = "test_data/test_data_fault_enospc.txt";
+        let _ = fs::remove_file(fpath);
+        let mut writer = SequentialWriter::new(fpath, 0, 4096, 2).unwrap();
+        writer.set_fault_plan(FaultPlan::new().fail_nth(3, libc::ENOSPC));
+        let line = b"abcdefghijklmnopqrstuvwxyz\n";
+        let err = (0..1000)
+            .try_for_each(|_| writer.write(line))
+            .and_then(|_| writer.finish())
+            .unwrap_err();
+        assert_eq!(io_errno(&err), Some(libc::ENOSPC));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fault_writer_short_writes() {
+        use crate::linux::fault::FaultPlan;
+        use std::time::Duration;
+
+        let fpath = "test_data/test_data_fault_short.txt";
+        let _ = fs::remove_file(fpath);
+        let mut expected = vec![];
+        let mut writer = SequentialWriter::new(fpath, 0, 4096, 3).unwrap();
+        writer.set_fault_plan(
+            FaultPlan::new()
+                .short_nth(1, 512)
+                .short_nth(2, 2048)
+                .delay_all(Duration::from_micros(100))
+                .reorder(),
+        );
+        for i in 0..1000 {
+            let line = format!("line:{}, abcdefghijklmnopqrstuvwxyz\n", i);
+            writer.write(line.as_bytes()).unwrap();
+            expected.extend_from_slice(line.as_bytes());
+        }
+        writer.finish().unwrap();
+        assert_eq!(fs::read(fpath).unwrap(), expected);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_io_context_shared_ring() {
+        use crate::IoContext;
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let ctx = IoContext::new(32, 8, 32).unwrap();
+        let mut readers = (0..4)
+            .map(|i| {
+                ctx.reader("test_data/test_data.txt", i * 1000, 4096, 4, None)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let fpath = "test_data/test_data_io_context.txt";
+        let _ = fs::remove_file(fpath);
+        let mut writer = ctx.writer(fpath, 0, 4096, 4).unwrap();
+
+        // interleave the streams so that each one reaps completions of the others
+        let mut outputs = vec![vec![]; readers.len()];
+        let mut buf = vec![0_u8; 3000];
+        loop {
+            let mut progress = false;
+            for (reader, out) in readers.iter_mut().zip(outputs.iter_mut()) {
+                let n = reader.read2buf(&mut buf).unwrap();
+                out.extend_from_slice(&buf[..n]);
+                progress |= n > 0;
+            }
+            ctx.poll().unwrap();
+            if !progress {
+                break;
+            }
+            writer.write(&buf).unwrap();
+        }
+        for (i, out) in outputs.iter().enumerate() {
+            assert_eq!(&out[..], &expected[i * 1000..]);
+        }
+        writer.finish().unwrap();
+        drop(readers);
+
+        // slots of the dropped streams are reusable
+        let mut reader = ctx
+            .reader("test_data/test_data.txt", 0, 4096, 16, None)
+            .unwrap();
+        assert_eq!(read_to_end(&mut reader).unwrap(), expected);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_io_context_blocked_stream() {
+        use crate::IoContext;
+        use io_uring::opcode;
+
+        // one stream waits in the kernel for a pipe, the others go on meanwhile
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let ctx = IoContext::new(16, 4, 8).unwrap();
+        let mut stream = ctx.open_stream(Some(fds[0]), &mut []).unwrap();
+        let blocked = std::thread::spawn(move || {
+            let mut buf = vec![0_u8; 16];
+            let read = opcode::Read::new(stream.file(), buf.as_mut_ptr(), buf.len() as u32)
+                .offset(u64::MAX)
+                .build()
+                .user_data(1);
+            unsafe { stream.push(read).unwrap() };
+            let n = stream.wait().unwrap().into_result().unwrap() as usize;
+            buf[..n].to_vec()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let mut reader = ctx
+            .reader("test_data/test_data.txt", 0, 4096, 4, None)
+            .unwrap();
+        assert_eq!(read_to_end(&mut reader).unwrap(), expected);
+
+        assert_eq!(unsafe { libc::write(fds[1], b"ping".as_ptr() as *const _, 4) }, 4);
+        assert_eq!(blocked.join().unwrap(), b"ping");
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_io_service_many_threads() {
+        use crate::IoService;
+
+        fn assert_send<T: Send>() {}
+        assert_send::<SequentialReader>();
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let service = IoService::new(32, 8, 32).unwrap();
+
+        let fpath = "test_data/test_data_io_service.txt";
+        let _ = fs::remove_file(fpath);
+        let out = service.open_file(fpath, true).unwrap();
+        let input = service.open_file("test_data/test_data.txt", false).unwrap();
+
+        std::thread::scope(|s| {
+            for i in 0..4_u64 {
+                let (input, out, expected) = (&input, &out, &expected);
+                s.spawn(move || {
+                    let offset = i * 1111;
+                    let data = input.read_at(offset, 5000).wait().unwrap();
+                    assert_eq!(&data[..], &expected[offset as usize..offset as usize + 5000]);
+                    let n = out.write_at(i * 5000, data).wait().unwrap();
+                    assert_eq!(n, 5000);
+                });
+            }
+            let service = &service;
+            let expected = &expected;
+            s.spawn(move || {
+                let reader = service
+                    .reader("test_data/test_data.txt", 10, 4096, 4, None)
+                    .unwrap();
+                let mut got = vec![];
+                let mut buf = vec![0_u8; 7000];
+                loop {
+                    let n = reader.read2buf(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    got.extend_from_slice(&buf[..n]);
+                }
+                assert_eq!(&got[..], &expected[10..]);
+            });
+        });
+        out.fsync().wait().unwrap();
+
+        let written = fs::read(fpath).unwrap();
+        for i in 0..4_usize {
+            assert_eq!(
+                &written[i * 5000..(i + 1) * 5000],
+                &expected[i * 1111..i * 1111 + 5000]
+            );
+        }
+        assert!(input.read_at(expected.len() as u64, 10).wait().unwrap().is_empty());
+        // one io_uring request takes at most u32::MAX bytes
+        assert!(input.read_at(0, u32::MAX as usize + 1).wait().is_err());
+
+        // queued stream requests complete in order while the service keeps going
+        let fpath = "test_data/test_data_io_service_writer.txt";
+        let _ = fs::remove_file(fpath);
+        let writer = service.writer(fpath, 0, 4096, 2).unwrap();
+        let writes = expected
+            .chunks(3000)
+            .map(|chunk| writer.write(chunk))
+            .collect::<Vec<_>>();
+        let data = input.read_at(0, 100).wait().unwrap();
+        assert_eq!(&data[..], &expected[..100]);
+        for write in writes {
+            write.wait().unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(fs::read(fpath).unwrap(), expected);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_buffer_pool_budget() {
+        use crate::{BufferPool, BufferPoolOptions, IoContext, ReaderConfig, WriterConfig};
+
+        let mut options = BufferPoolOptions::new(4096, 6 * 4096);
+        options.alloc.mlock = true;
+        let pool = BufferPool::new(options).unwrap();
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+
+        let ctx = IoContext::new(16, 4, 8).unwrap();
+        let mut config = ReaderConfig::new(4096, 4);
+        config.pool = Some(pool.clone());
+        let mut reader = ctx
+            .reader_with_config("test_data/test_data.txt", 0, None, config)
+            .unwrap();
+        assert_eq!(pool.available(), 2);
+        assert!(pool.try_acquire(3).unwrap().is_none());
+
+        let fpath = "test_data/test_data_buffer_pool.txt";
+        let _ = fs::remove_file(fpath);
+        let mut config = WriterConfig::new(4096, 2);
+        config.pool = Some(pool.clone());
+        let mut writer = ctx.writer_with_config(fpath, 0, config).unwrap();
+        assert_eq!(pool.available(), 0);
+        assert_eq!(pool.allocated_bytes(), 6 * 4096);
+
+        let data = read_to_end(&mut reader).unwrap();
+        assert_eq!(data, expected);
+        writer.write(&data).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(fs::read(fpath).unwrap(), expected);
+
+        // returned buffers are reused, still registered in the context
+        drop(reader);
+        drop(writer);
+        assert_eq!(pool.available(), 6);
+        let mut config = ReaderConfig::new(4096, 6);
+        config.pool = Some(pool.clone());
+        let mut reader = ctx
+            .reader_with_config("test_data/test_data.txt", 0, None, config)
+            .unwrap();
+        assert_eq!(read_to_end(&mut reader).unwrap(), expected);
+        assert_eq!(pool.allocated_bytes(), 6 * 4096);
+        assert!(pool.acquire(7).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_huge_page_buffers() {
+        use crate::linux::buffer::{AlignedVecU8, AllocOptions, HugePages, HUGE_PAGE_SIZE};
+        use crate::ReaderConfig;
+
+        let options = AllocOptions {
+            huge_pages: HugePages::Transparent,
+            mlock: true,
+        };
+        let buf = AlignedVecU8::with_options(HUGE_PAGE_SIZE, 4096, options).unwrap();
+        assert_eq!(buf.as_ptr() as usize % HUGE_PAGE_SIZE, 0);
+        assert!(buf.is_locked());
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        for huge_pages in [HugePages::Transparent, HugePages::Explicit] {
+            let mut config = ReaderConfig::new(64 * 1024, 2);
+            config.alloc = AllocOptions {
+                huge_pages,
+                mlock: true,
+            };
+            let mut reader =
+                SequentialReader::with_config("test_data/test_data.txt", 0, None, config).unwrap();
+            assert_eq!(read_to_end(&mut reader).unwrap(), expected);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_provided_buffers_reader() {
+        use crate::{IoContext, ReaderConfig};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let mut config = ReaderConfig::new(4096, 4);
+        config.provided_buffers = true;
+        let mut reader =
+            SequentialReader::with_config("test_data/test_data.txt", 0, None, config.clone())
+                .unwrap();
+        assert_eq!(read_to_end(&mut reader).unwrap(), expected);
+
+        // buffer groups of a shared ring are released with their streams
+        let ctx = IoContext::new(32, 8, 32).unwrap();
+        for _ in 0..3 {
+            let end_pos = expected.len() as u64 - 123;
+            let mut reader = ctx
+                .reader_with_config("test_data/test_data.txt", 5000, Some(end_pos), config.clone())
+                .unwrap();
+            assert_eq!(
+                read_to_end(&mut reader).unwrap(),
+                &expected[5000..end_pos as usize]
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timeouts() {
+        use crate::{IoContext, ReaderConfig};
+        use io_uring::opcode;
+        use std::time::{Duration, Instant};
+
+        // requests that finish in time are not affected
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let mut config = ReaderConfig::new(4096, 4);
+        config.timeout = Some(Duration::from_secs(10));
+        let mut reader =
+            SequentialReader::with_config("test_data/test_data.txt", 0, None, config).unwrap();
+        let mut buf = vec![0_u8; 10000];
+        let n = reader
+            .read2buf_timeout(&mut buf, Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(&buf[..n], &expected[..n]);
+        let mut out = buf[..n].to_vec();
+        out.extend(read_to_end(&mut reader).unwrap());
+        assert_eq!(out, expected);
+
+        // nothing is ever written to the pipe, so reads from it never finish
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let ctx = IoContext::new(8, 2, 2).unwrap();
+        let mut stream = ctx.open_stream(Some(fds[0]), &mut []).unwrap();
+        let mut buf = vec![0_u8; 16];
+        let read = opcode::Read::new(stream.file(), buf.as_mut_ptr(), buf.len() as u32)
+            .offset(u64::MAX)
+            .build()
+            .user_data(1);
+
+        stream.set_timeout(Some(Duration::from_millis(20)));
+        unsafe { stream.push(read.clone()).unwrap() };
+        let err = stream.wait().unwrap().into_result().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        stream.set_timeout(None);
+        unsafe { stream.push(read.clone()).unwrap() };
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(20);
+        let err = stream
+            .wait_deadline(Some(deadline))
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // a cancellation that is not a timeout stays one
+        unsafe { stream.push(read).unwrap() };
+        stream.cancel().unwrap();
+        let err = stream.wait().unwrap().into_result().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ECANCELED));
+
+        drop(stream);
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_try_read_write() {
+        use std::io::ErrorKind;
+
+        fn would_block(err: &anyhow::Error) -> bool {
+            err.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == ErrorKind::WouldBlock)
+        }
+
+        fn wait_readable(fd: std::os::fd::RawFd) {
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            assert_eq!(unsafe { libc::poll(&mut pfd, 1, 5000) }, 1);
+        }
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let mut reader = SequentialReader::new("test_data/test_data.txt", 0, 4096, 4, None).unwrap();
+        let reader_fd = reader.eventfd().unwrap();
+        let fpath = "test_data/test_data_try_write.txt";
+        let _ = fs::remove_file(fpath);
+        let mut writer = SequentialWriter::new(fpath, 0, 4096, 4).unwrap();
+        let writer_fd = writer.eventfd().unwrap();
+
+        let mut out = vec![];
+        let mut buf = vec![0_u8; 5000];
+        loop {
+            let n = match reader.try_read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if would_block(&e) => {
+                    wait_readable(reader_fd);
+                    continue;
+                }
+                Err(e) => panic!("{:?}", e),
+            };
+            out.extend_from_slice(&buf[..n]);
+
+            let mut pending = &buf[..n];
+            while !pending.is_empty() {
+                match writer.try_write(pending) {
+                    Ok(n) => pending = &pending[n..],
+                    Err(e) if would_block(&e) => wait_readable(writer_fd),
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        }
+        assert_eq!(out, expected);
+        writer.finish().unwrap();
+        assert_eq!(fs::read(fpath).unwrap(), expected);
+    }
+
+    #[cfg(all(target_os = "linux", feature = "tokio"))]
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_tokio_async_io() {
+        use crate::{AsyncSequentialReader, AsyncSequentialWriter};
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let reader = SequentialReader::new("test_data/test_data.txt", 0, 4096, 4, None).unwrap();
+        let mut reader = AsyncSequentialReader::new(reader).unwrap();
+        let mut first_line = String::new();
+        reader.read_line(&mut first_line).await.unwrap();
+        let mut out = first_line.into_bytes();
+        reader.read_to_end(&mut out).await.unwrap();
+        assert_eq!(out, expected);
+
+        let fpath = "test_data/test_data_tokio_writer.txt";
+        let _ = fs::remove_file(fpath);
+        let writer = SequentialWriter::new(fpath, 0, 4096, 2).unwrap();
+        let mut writer = AsyncSequentialWriter::new(writer).unwrap();
+        let mid = expected.len() / 2 + 123;
+        for chunk in expected[..mid].chunks(3000) {
+            writer.write_all(chunk).await.unwrap();
+        }
+        // flush writes the unaligned tail as well
+        writer.flush().await.unwrap();
+        assert_eq!(fs::read(fpath).unwrap(), &expected[..mid]);
+        for chunk in expected[mid..].chunks(3000) {
+            writer.write_all(chunk).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(fs::read(fpath).unwrap(), expected);
+    }
+
+    #[cfg(all(target_os = "linux", feature = "futures"))]
+    #[test]
+    fn test_futures_async_io() {
+        use crate::{AsyncSequentialReader, AsyncSequentialWriter};
+        use futures::{AsyncReadExt, AsyncWriteExt};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        futures::executor::block_on(async {
+            let reader =
+                SequentialReader::new("test_data/test_data.txt", 100, 4096, 4, None).unwrap();
+            let mut reader = AsyncSequentialReader::new(reader).unwrap();
+            let mut out = vec![];
+            reader.read_to_end(&mut out).await.unwrap();
+            assert_eq!(out, &expected[100..]);
+
+            let fpath = "test_data/test_data_futures_writer.txt";
+            let _ = fs::remove_file(fpath);
+            let writer = SequentialWriter::new(fpath, 0, 4096, 2).unwrap();
+            let mut writer = AsyncSequentialWriter::new(writer).unwrap();
+            writer.write_all(&out).await.unwrap();
+            writer.close().await.unwrap();
+            assert_eq!(fs::read(fpath).unwrap(), &expected[100..]);
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_random_reader() {
+        use crate::{IoContext, RandomReader};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let size = expected.len() as u64;
+        // overlapping, adjacent, unaligned, empty, out of order and up to the end of the file
+        let ranges = [
+            (5000, 100),
+            (10, 20),
+            (20, 4100),
+            (4120, 3),
+            (size - 7, 7),
+            (300_000, 0),
+            (8192, 4096),
+            (100_000, 30_000),
+            (12, 4),
+        ];
+        let mut reader = RandomReader::new("test_data/test_data.txt", 4).unwrap();
+        let ctx = IoContext::new(16, 2, 2).unwrap();
+        let mut shared = ctx.random_reader("test_data/test_data.txt", 4).unwrap();
+        for reader in [&mut reader, &mut shared] {
+            let out = reader.read_many(&ranges).unwrap();
+            for (&(offset, len), data) in ranges.iter().zip(out.iter()) {
+                assert_eq!(&data[..], &expected[offset as usize..offset as usize + len]);
+            }
+        }
+        assert!(reader.read_many(&[(size - 1, 2)]).is_err());
+        assert!(reader.read_many(&[(u64::MAX, 2)]).is_err());
+
+        // the spans that fit a pool buffer are read into registered ones, the others allocated
+        let pool = crate::BufferPool::new(crate::BufferPoolOptions::new(8192, 2 * 8192)).unwrap();
+        let mut pooled = RandomReader::with_pool("test_data/test_data.txt", 4, pool.clone()).unwrap();
+        let mut shared = ctx
+            .random_reader_with_pool("test_data/test_data.txt", 4, pool.clone())
+            .unwrap();
+        for reader in [&mut pooled, &mut shared] {
+            let out = reader.read_many(&ranges).unwrap();
+            // only the page at the end of the file fits
+            assert_eq!(pool.available(), 1);
+            for (&(offset, len), data) in ranges.iter().zip(out.iter()) {
+                assert_eq!(&data[..], &expected[offset as usize..offset as usize + len]);
+            }
+            drop(out);
+            assert_eq!(pool.available(), 2);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_random_writer() {
+        use crate::{IoContext, RandomWriter};
+
+        let ctx = IoContext::new(16, 2, 2).unwrap();
+        for (i, fpath) in ["test_data/random_writer_0.bin", "test_data/random_writer_1.bin"]
+            .iter()
+            .enumerate()
+        {
+            let mut model = (0..10_000u32).map(|v| (v % 251) as u8).collect::<Vec<_>>();
+            fs::write(fpath, &model).unwrap();
+            let mut writer = if i == 0 {
+                RandomWriter::new(fpath, 4).unwrap()
+            } else {
+                ctx.random_writer(fpath, 4).unwrap()
+            };
+
+            // unaligned, overlapping, adjacent, empty, page sized and past the end of the file
+            let batches: Vec<Vec<(u64, Vec<u8>)>> = vec![
+                vec![
+                    (10, vec![1; 20]),
+                    (25, vec![2; 10]),
+                    (35, vec![3; 5000]),
+                    (8192, vec![4; 4096]),
+                    (700, vec![]),
+                    (9_990, vec![5; 30]),
+                ],
+                vec![(20_000, vec![6; 100]), (4095, vec![7; 2])],
+                vec![(12_000, vec![8; 3])],
+            ];
+            for (j, batch) in batches.iter().enumerate() {
+                let writes = batch
+                    .iter()
+                    .map(|(offset, data)| (*offset, data.as_slice()))
+                    .collect::<Vec<_>>();
+                if j == 1 {
+                    writer.write_many_and_sync(&writes).unwrap();
+                } else {
+                    writer.write_many(&writes).unwrap();
+                }
+                for (offset, data) in batch {
+                    let end = *offset as usize + data.len();
+                    if model.len() < end {
+                        model.resize(end, 0);
+                    }
+                    model[*offset as usize..end].copy_from_slice(data);
+                }
+                assert_eq!(writer.file_size(), model.len() as u64);
+                assert_eq!(fs::read(fpath).unwrap(), model);
+            }
+            fs::remove_file(fpath).unwrap();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_direct_file() {
+        use crate::{BufferPool, BufferPoolOptions, DirectFile, IoContext};
+
+        let fpath = "test_data/direct_file.bin";
+        let _ = fs::remove_file(fpath);
+        let mut file = DirectFile::create(fpath, 8).unwrap();
+        let handle = file.fallocate(0, 3 * 4096, 0).unwrap();
+        file.wait(handle).unwrap();
+        assert_eq!(file.file_size().unwrap(), 3 * 4096);
+
+        // several writes in flight, waited for out of order
+        let handles = (0..3u8)
+            .map(|i| {
+                let mut buf = DirectFile::alloc_buffer(4096).unwrap();
+                buf.fill(i + 1);
+                buf.len = 4096;
+                file.write_at(buf, i as u64 * 4096).unwrap()
+            })
+            .collect::<Vec<_>>();
+        for handle in handles.into_iter().rev() {
+            assert_eq!(file.wait(handle).unwrap().len, 4096);
+        }
+        let handle = file.fdatasync().unwrap();
+        file.wait(handle).unwrap();
+
+        let handle = file.read_at(DirectFile::alloc_buffer(8192).unwrap(), 4096).unwrap();
+        let out = match file.poll(handle).unwrap() {
+            Ok(out) => out,
+            Err(handle) => {
+                file.wait_all().unwrap();
+                file.poll(handle).unwrap().ok().unwrap()
+            }
+        };
+        let buf = out.buf.unwrap();
+        assert_eq!(buf.len(), 8192);
+        assert!(buf[..4096].iter().all(|&b| b == 2));
+        assert!(buf[4096..8192].iter().all(|&b| b == 3));
+
+        let handle = file.set_len(5000).unwrap();
+        file.wait(handle).unwrap();
+        let handle = file.fsync().unwrap();
+        file.wait(handle).unwrap();
+        assert_eq!(fs::metadata(fpath).unwrap().len(), 5000);
+        // the read stops at the end of the file
+        let handle = file.read_at(DirectFile::alloc_buffer(8192).unwrap(), 0).unwrap();
+        assert_eq!(file.wait(handle).unwrap().len, 5000);
+        assert_eq!(DirectFile::alloc_buffer(100).unwrap().cap(), 4096);
+        assert!(file.read_at(DirectFile::alloc_buffer(4096).unwrap(), 10).is_err());
+        drop(file);
+
+        let ctx = IoContext::new(16, 2, 2).unwrap();
+        let mut file = ctx
+            .direct_file(fpath, fs::OpenOptions::new().read(true), 4)
+            .unwrap();
+        let handle = file.read_at(DirectFile::alloc_buffer(4096).unwrap(), 0).unwrap();
+        let out = file.wait(handle).unwrap();
+        assert!(out.buf.unwrap()[..4096].iter().all(|&b| b == 1));
+        drop(file);
+
+        // registered buffers go through ReadFixed / WriteFixed
+        let pool = BufferPool::new(BufferPoolOptions::new(4096, 2 * 4096)).unwrap();
+        for ctx in [None, Some(&ctx)] {
+            let mut options = fs::OpenOptions::new();
+            options.read(true).write(true);
+            let mut file = match ctx {
+                Some(ctx) => ctx.direct_file_with_pool(fpath, &mut options, 4, pool.clone()),
+                None => DirectFile::with_pool(fpath, &mut options, 4, pool.clone()),
+            }
+            .unwrap();
+            let mut buf = file.buffer().unwrap().unwrap();
+            let other = file.buffer().unwrap().unwrap();
+            assert!(file.buffer().unwrap().is_none());
+            buf.fill(7);
+            buf.len = 4096;
+            let handle = file.write_at(buf, 4096).unwrap();
+            let buf = file.wait(handle).unwrap().buf.unwrap();
+            drop(buf);
+            let handle = file.read_at(other, 4096).unwrap();
+            let out = file.wait(handle).unwrap();
+            assert_eq!(out.len, 4096);
+            assert!(out.buf.unwrap().iter().all(|&b| b == 7));
+        }
+        assert!(DirectFile::open(fpath, 4).unwrap().buffer().is_err());
+        fs::remove_file(fpath).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_writer_durability() {
+        use crate::{Durability, SequentialWriter, WriterConfig};
+        use std::time::Duration;
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let policies = [
+            Durability::None,
+            Durability::OnFinish,
+            Durability::EveryBytes(3 * 4096),
+            Durability::EveryInterval(Duration::ZERO),
+            Durability::PerWrite,
+        ];
+        for (i, durability) in policies.into_iter().enumerate() {
+            let fpath = format!("test_data/durability_{}.bin", i);
+            let _ = fs::remove_file(&fpath);
+            let mut config = WriterConfig::new(4096, 4);
+            config.durability = durability;
+            config.timeout = (i == 2).then(|| Duration::from_secs(10));
+            let mut writer = SequentialWriter::with_config(&fpath, 0, config).unwrap();
+            let mid = expected.len() / 2 + 123;
+            for chunk in expected[..mid].chunks(1000) {
+                writer.write(chunk).unwrap();
+            }
+            // everything written so far is in the file, without the padding of the last page
+            writer.sync().unwrap();
+            assert_eq!(fs::read(&fpath).unwrap(), &expected[..mid]);
+            for chunk in expected[mid..].chunks(777) {
+                writer.write(chunk).unwrap();
+            }
+            writer.finish().unwrap();
+            drop(writer);
+            assert_eq!(fs::read(&fpath).unwrap(), expected, "{:?}", durability);
+            fs::remove_file(&fpath).unwrap();
+        }
+
+        // a failed periodic fdatasync is reported, not deferred
+        let fpath = "test_data/durability_fail.bin";
+        let _ = fs::remove_file(fpath);
+        let mut config = WriterConfig::new(4096, 4);
+        config.durability = Durability::EveryBytes(8 * 4096);
+        let mut writer = SequentialWriter::with_config(fpath, 0, config).unwrap();
+        // requests 0 to 7 are the first eight writes, 8 the fdatasync after them
+        writer.set_fault_plan(crate::linux::fault::FaultPlan::new().fail_nth(8, libc::EIO));
+        let err = expected[..9 * 4096]
+            .chunks(1000)
+            .try_for_each(|chunk| writer.write(chunk))
+            .and_then(|_| writer.finish())
+            .unwrap_err();
+        assert_eq!(io_errno(&err), Some(libc::EIO));
+        drop(writer);
+        fs::remove_file(fpath).unwrap();
+
+        // a periodic fdatasync only waits for its own write, the writes after it are submitted
+        // and run while it is pending
+        let fpath = "test_data/durability_linked.bin";
+        let _ = fs::remove_file(fpath);
+        let mut config = WriterConfig::new(4096, 4);
+        config.durability = Durability::EveryBytes(4096);
+        let mut writer = SequentialWriter::with_config(fpath, 0, config).unwrap();
+        assert_eq!(writer.try_write(&expected[..4 * 4096]).unwrap(), 4 * 4096);
+        assert_eq!(writer.in_flight(), (4, 4));
+        let mut written = 4 * 4096;
+        while written < expected.len() {
+            match writer.try_write(&expected[written..]) {
+                Ok(n) => written += n,
+                Err(e) if crate::linux::utils::would_block(&e) => {
+                    std::thread::sleep(Duration::from_millis(1))
+                }
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+        writer.finish().unwrap();
+        drop(writer);
+        assert_eq!(fs::read(fpath).unwrap(), expected);
+        fs::remove_file(fpath).unwrap();
+
+        // syncing inside a longer file must not pad over the old data after the written part
+        let fpath = "test_data/durability_overwrite.bin";
+        let old = vec![b'x'; expected.len() + 20000];
+        fs::write(fpath, &old).unwrap();
+        let mut writer = SequentialWriter::new(fpath, 0, 4096, 4).unwrap();
+        let mid = 5000;
+        writer.write(&expected[..mid]).unwrap();
+        writer.sync().unwrap();
+        let out = fs::read(fpath).unwrap();
+        assert_eq!(&out[..mid], &expected[..mid]);
+        assert_eq!(&out[mid..], &old[mid..]);
+        writer.write(&expected[mid..]).unwrap();
+        writer.sync().unwrap();
+        writer.finish().unwrap();
+        let out = fs::read(fpath).unwrap();
+        assert_eq!(&out[..expected.len()], &expected[..]);
+        assert_eq!(&out[expected.len()..], &old[expected.len()..]);
+        fs::remove_file(fpath).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_writer_preallocation() {
+        use crate::{Preallocation, SequentialWriter, WriterConfig};
+        use std::os::unix::fs::MetadataExt;
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let size = expected.len() as u64;
+        let cases = [
+            Preallocation::UpFront {
+                expected_size: size + 100_000,
+                keep_size: false,
+            },
+            Preallocation::UpFront {
+                expected_size: size,
+                keep_size: true,
+            },
+            Preallocation::Chunked {
+                chunk: 64 * 1024,
+                keep_size: false,
+            },
+            Preallocation::Chunked {
+                chunk: 3000,
+                keep_size: true,
+            },
+        ];
+        for (i, preallocation) in cases.into_iter().enumerate() {
+            let fpath = format!("test_data/preallocation_{}.bin", i);
+            let _ = fs::remove_file(&fpath);
+            let mut config = WriterConfig::new(4096, 4);
+            config.preallocation = preallocation;
+            let mut writer = SequentialWriter::with_config(&fpath, 0, config).unwrap();
+            if i == 0 {
+                // reserved before anything is written
+                let meta = fs::metadata(&fpath).unwrap();
+                assert_eq!(meta.len(), size + 100_000);
+                assert!(meta.blocks() * 512 >= size + 100_000);
+            }
+            for chunk in expected.chunks(1000) {
+                writer.write(chunk).unwrap();
+            }
+            writer.finish().unwrap();
+            drop(writer);
+            assert_eq!(fs::read(&fpath).unwrap(), expected, "{:?}", preallocation);
+            fs::remove_file(&fpath).unwrap();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_writer_create_atomic() {
+        use crate::SequentialWriter;
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let fpath = "test_data/atomic.bin";
+        let _ = fs::remove_file(fpath);
+
+        let mut writer = SequentialWriter::create_atomic(fpath, 4096, 4).unwrap();
+        writer.write(&expected).unwrap();
+        writer.finish().unwrap();
+        assert!(fs::metadata(fpath).is_err());
+        writer.commit().unwrap();
+        drop(writer);
+        assert_eq!(fs::read(fpath).unwrap(), expected);
+
+        // dropped without commit, the old file stays
+        let mut writer = SequentialWriter::create_atomic(fpath, 4096, 4).unwrap();
+        writer.write(b"partial").unwrap();
+        drop(writer);
+        assert_eq!(fs::read(fpath).unwrap(), expected);
+
+        // replaces the old file
+        let mut writer = SequentialWriter::create_atomic(fpath, 4096, 4).unwrap();
+        writer.write(&expected[..5000]).unwrap();
+        writer.commit().unwrap();
+        drop(writer);
+        assert_eq!(fs::read(fpath).unwrap(), &expected[..5000]);
+
+        let leftovers = fs::read_dir("test_data")
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with(".atomic.bin.tmp")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+        fs::remove_file(fpath).unwrap();
+
+        let mut writer = SequentialWriter::new("test_data/not_atomic.bin", 0, 4096, 1).unwrap();
+        assert!(writer.commit().is_err());
+        drop(writer);
+        fs::remove_file("test_data/not_atomic.bin").unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_stream_locks() {
+        use crate::{LockMode, Locked, ReaderConfig, SequentialReader, SequentialWriter, WriterConfig};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let fpath = "test_data/locked.bin";
+        let _ = fs::remove_file(fpath);
+        let is_locked = |e: anyhow::Error| e.downcast_ref::<Locked>().is_some();
+        let mut wconfig = WriterConfig::new(4096, 2);
+        wconfig.lock = LockMode::Try;
+        let mut rconfig = ReaderConfig::new(4096, 2);
+        rconfig.lock = LockMode::Try;
+
+        let mut writer = SequentialWriter::with_config(fpath, 0, wconfig.clone()).unwrap();
+        writer.write(&expected).unwrap();
+        let Err(e) = SequentialWriter::with_config(fpath, 0, wconfig.clone()) else {
+            panic!("second writer got the lock");
+        };
+        assert!(is_locked(e));
+        let Err(e) = SequentialReader::with_config(fpath, 0, None, rconfig.clone()) else {
+            panic!("reader got the lock of a writer");
+        };
+        assert!(is_locked(e));
+        // streams without a lock are not kept out
+        drop(SequentialReader::new(fpath, 0, 4096, 2, None).unwrap());
+        writer.finish().unwrap();
+
+        // readers share the lock, writers wait for it
+        let reader1 = SequentialReader::with_config(fpath, 0, None, rconfig.clone()).unwrap();
+        let mut reader2 = SequentialReader::with_config(fpath, 0, None, rconfig).unwrap();
+        assert_eq!(read_to_end(&mut reader2).unwrap(), expected);
+        let Err(e) = SequentialWriter::with_config(fpath, 0, wconfig.clone()) else {
+            panic!("writer got the lock of readers");
+        };
+        assert!(is_locked(e));
+        let handle = std::thread::spawn(|| {
+            let mut config = WriterConfig::new(4096, 2);
+            config.lock = LockMode::Blocking;
+            let mut writer = SequentialWriter::with_config(fpath, 0, config).unwrap();
+            writer.write(b"LOCKED").unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(reader1);
+        drop(reader2);
+        handle.join().unwrap();
+        let mut out = fs::read(fpath).unwrap();
+        assert_eq!(&out[..6], b"LOCKED");
+        out[..6].copy_from_slice(&expected[..6]);
+        assert_eq!(out, expected);
+        fs::remove_file(fpath).unwrap();
+
+        // atomic writers lock the final path, not their temp files
+        let fpath = "test_data/locked_atomic.bin";
+        let mut first = SequentialWriter::create_atomic_with_config(fpath, wconfig.clone()).unwrap();
+        first.write(b"first").unwrap();
+        let Err(e) = SequentialWriter::create_atomic_with_config(fpath, wconfig.clone()) else {
+            panic!("second atomic writer got the lock");
+        };
+        assert!(is_locked(e));
+        let handle = std::thread::spawn(move || {
+            let mut config = WriterConfig::new(4096, 2);
+            config.lock = LockMode::Blocking;
+            let mut second = SequentialWriter::create_atomic_with_config(fpath, config).unwrap();
+            // the first one is in place before the second one starts
+            assert_eq!(fs::read(fpath).unwrap(), b"first");
+            second.write(b"second").unwrap();
+            second.commit().unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        first.commit().unwrap();
+        handle.join().unwrap();
+        assert_eq!(fs::read(fpath).unwrap(), b"second");
+        // released on commit
+        drop(SequentialWriter::create_atomic_with_config(fpath, wconfig).unwrap());
+        fs::remove_file(fpath).unwrap();
+        // the lock file stays for the next writer
+        assert!(fs::metadata("test_data/.locked_atomic.bin.lock").is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_reader_follow() {
+        use crate::{FileChange, FileChanged, ReaderConfig, SequentialReader};
+        use std::{io::Write, time::Duration};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let fpath = "test_data/follow.log";
+        fs::write(fpath, &expected[..5000]).unwrap();
+        let mut config = ReaderConfig::new(4096, 2);
+        config.follow = Some(Duration::from_millis(5));
+        let mut reader = SequentialReader::with_config(fpath, 0, None, config.clone()).unwrap();
+
+        // appended in unaligned pieces while the reader waits at the end
+        let appender = std::thread::spawn({
+            let rest = expected[5000..].to_vec();
+            move || {
+                let mut f = fs::OpenOptions::new().append(true).open(fpath).unwrap();
+                for piece in rest.chunks(33_333) {
+                    std::thread::sleep(Duration::from_millis(5));
+                    f.write_all(piece).unwrap();
+                }
+            }
+        });
+        let mut out = vec![];
+        let mut buf = vec![0_u8; 10_000];
+        while out.len() < expected.len() {
+            let n = reader.read2buf(&mut buf).unwrap();
+            out.extend_from_slice(&buf[..n]);
+        }
+        appender.join().unwrap();
+        assert_eq!(out, expected);
+
+        // nothing new
+        assert_eq!(
+            io_kind(&reader.try_read(&mut buf).unwrap_err()),
+            Some(std::io::ErrorKind::WouldBlock)
+        );
+        let err = reader
+            .read2buf_timeout(&mut buf, Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::TimedOut));
+
+        let change = |err: anyhow::Error| err.downcast_ref::<FileChanged>().map(|e| e.change);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(fpath)
+            .unwrap()
+            .set_len(10)
+            .unwrap();
+        let mut reader = SequentialReader::with_config(fpath, 0, None, config).unwrap();
+        assert_eq!(reader.read2buf(&mut buf).unwrap(), 10);
+        fs::write("test_data/follow.log.new", b"rotated").unwrap();
+        fs::rename("test_data/follow.log.new", fpath).unwrap();
+        assert_eq!(
+            change(reader.read2buf(&mut buf).unwrap_err()),
+            Some(FileChange::Replaced)
+        );
+
+        let mut config = ReaderConfig::new(4096, 2);
+        config.follow = Some(Duration::from_millis(5));
+        let mut reader = SequentialReader::with_config(fpath, 0, None, config).unwrap();
+        assert_eq!(reader.read2buf(&mut buf).unwrap(), 7);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(fpath)
+            .unwrap()
+            .set_len(3)
+            .unwrap();
+        assert_eq!(
+            change(reader.read2buf(&mut buf).unwrap_err()),
+            Some(FileChange::Truncated)
+        );
+
+        // the unaligned end is read from the open file, not from what the path names by now
+        let len = 3 * 4096 + 100;
+        fs::write(fpath, &expected[..len]).unwrap();
+        let mut reader = SequentialReader::new(fpath, 0, 4096, 2, None).unwrap();
+        let mut out = vec![0_u8; 4096];
+        reader.read2buf(&mut out).unwrap();
+        fs::write("test_data/follow.log.new", &expected[len..2 * len]).unwrap();
+        fs::rename("test_data/follow.log.new", fpath).unwrap();
+        loop {
+            let n = reader.read2buf(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, &expected[..len]);
+        fs::remove_file(fpath).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_reverse_reader() {
+        use crate::{IoContext, ReaderConfig, ReverseReader};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let size = expected.len() as u64;
+        let ctx = IoContext::new(16, 2, 2).unwrap();
+        for (start, end) in [(0, None), (100, Some(size - 5000)), (4096, Some(8192)), (7, Some(7))] {
+            let mut reader = ReverseReader::new("test_data/test_data.txt", start, end, 4096, 3).unwrap();
+            let mut chunks = vec![];
+            while let Some(chunk) = reader.next_chunk().unwrap() {
+                chunks.push(chunk.to_vec());
+            }
+            let out = chunks.into_iter().rev().flatten().collect::<Vec<_>>();
+            let end = end.unwrap_or(size) as usize;
+            assert_eq!(out, &expected[start as usize..end]);
+            assert!(reader.next_chunk().unwrap().is_none());
+        }
+
+        let config = ReaderConfig::new(8192, 2);
+        let reader = ctx
+            .reverse_reader("test_data/test_data.txt", 0, None, config)
+            .unwrap();
+        let lines = reader.lines().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        let mut want = expected.split(|&b| b == b'\n').collect::<Vec<_>>();
+        if expected.last() == Some(&b'\n') {
+            want.pop();
+        }
+        want.reverse();
+        assert_eq!(lines, want);
+
+        let fpath = "test_data/reverse_lines.txt";
+        for (data, want) in [
+            (&b"a\nbb\n\nccc"[..], vec![&b"ccc"[..], b"", b"bb", b"a"]),
+            (b"a\n", vec![b"a"]),
+            (b"\n", vec![b""]),
+            (b"", vec![]),
+            (b"a\r\nb\r\n", vec![b"b", b"a"]),
+        ] {
+            fs::write(fpath, data).unwrap();
+            let reader = ReverseReader::new(fpath, 0, None, 4096, 2).unwrap();
+            let lines = reader.lines().collect::<anyhow::Result<Vec<_>>>().unwrap();
+            assert_eq!(lines, want);
+        }
+        // a line across many blocks
+        let long = vec![b'x'; 5 * 4096 + 123];
+        let mut data = b"first\n".to_vec();
+        data.extend_from_slice(&long);
+        data.extend_from_slice(b"\nlast");
+        fs::write(fpath, &data).unwrap();
+        let reader = ReverseReader::new(fpath, 0, None, 4096, 2).unwrap();
+        let lines = reader.lines().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(lines, vec![b"last".to_vec(), long, b"first".to_vec()]);
+        fs::remove_file(fpath).unwrap();
+    }
+
+    #[test]
+    fn test_multi_file_reader() {
+        use crate::{FileRange, MultiFileReader, Origin};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let size = expected.len() as u64;
+        let parts = ["test_data/multi_0.txt", "test_data/multi_1.txt", "test_data/multi_2.txt"];
+        fs::write(parts[0], &expected[..5000]).unwrap();
+        fs::write(parts[1], b"").unwrap();
+        fs::write(parts[2], &expected[5000..]).unwrap();
+
+        let files = vec![
+            FileRange::from(parts[0]),
+            FileRange::from(parts[1]),
+            FileRange::from(parts[2]),
+            FileRange::new("test_data/test_data.txt", 100, Some(9000)),
+        ];
+        let mut want = expected.clone();
+        want.extend_from_slice(&expected[100..9000]);
+        let mut reader = MultiFileReader::new(files.clone(), 4096, 2).unwrap();
+        let mut out = vec![0u8; want.len() + 10];
+        assert_eq!(reader.read2buf(&mut out).unwrap(), want.len());
+        assert_eq!(&out[..want.len()], &want);
+        assert_eq!(reader.position().unwrap(), None);
+
+        // a pool with buffers for one file at a time, the next file is not read ahead
+        let pool = crate::BufferPool::new(crate::BufferPoolOptions::new(4096, 2 * 4096)).unwrap();
+        let mut config = crate::ReaderConfig::new(4096, 2);
+        config.pool = Some(pool.clone());
+        let mut reader = MultiFileReader::with_config(files.clone(), config).unwrap();
+        let mut out = vec![0u8; want.len()];
+        assert_eq!(reader.read2buf(&mut out).unwrap(), want.len());
+        assert_eq!(out, want);
+        drop(reader);
+        assert_eq!(pool.available(), 2);
+
+        let mut reader = MultiFileReader::new(files, 4096, 2).unwrap();
+        assert_eq!(reader.position().unwrap(), Some((0, 0)));
+        let mut buf = vec![0u8; 3000];
+        let mut out = vec![];
+        let mut origins = vec![];
+        while let Some(origin) = reader.read_segment(&mut buf).unwrap() {
+            out.extend_from_slice(&buf[..origin.len]);
+            origins.push(origin);
+        }
+        assert_eq!(out, want);
+        let seg = |file_idx, offset, len| Origin { file_idx, offset, len };
+        assert_eq!(&origins[..3], &[seg(0, 0, 3000), seg(0, 3000, 2000), seg(2, 0, 3000)]);
+        assert!(origins.iter().all(|o| o.file_idx != 1));
+        let last_file = origins.iter().filter(|o| o.file_idx == 3).collect::<Vec<_>>();
+        assert_eq!(last_file[0].offset, 100);
+        assert_eq!(last_file.iter().map(|o| o.len as u64).sum::<u64>(), 8900);
+        assert_eq!(
+            origins.iter().filter(|o| o.file_idx == 2).map(|o| o.len as u64).sum::<u64>(),
+            size - 5000
+        );
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parallel_reader() {
+        use crate::{ParallelSequentialReader, ReaderConfig, StripeConfig};
+
+        let expected = fs::read("test_data/test_data.txt").unwrap();
+        let size = expected.len() as u64;
+        for (start, end, stripe_size, num_threads) in [
+            (0, None, 4096, 3),
+            (100, Some(size - 777), 8192, 2),
+            (5000, Some(5000), 4096, 2),
+            (0, None, 1 << 20, 4),
+        ] {
+            let stripe = StripeConfig::new(stripe_size, num_threads);
+            let mut reader =
+                ParallelSequentialReader::new("test_data/test_data.txt", start, end, stripe, 4096, 2)
+                    .unwrap();
+            let mut out = vec![0u8; size as usize + 10];
+            let n = reader.read2buf(&mut out).unwrap();
+            let end = end.unwrap_or(size) as usize;
+            assert_eq!(&out[..n], &expected[start as usize..end]);
+            assert_eq!(reader.read2buf(&mut out).unwrap(), 0);
+        }
+
+        // buffers larger than a stripe, handed out without copying
+        let stripe = StripeConfig::new(8192, 3);
+        let mut reader =
+            ParallelSequentialReader::new("test_data/test_data.txt", 100, None, stripe, 3 * 4096, 2)
+                .unwrap();
+        let mut got = vec![];
+        loop {
+            let data = reader.fill_buf().unwrap();
+            if data.is_empty() {
+                break;
+            }
+            assert!(data.len() <= 8192);
+            let n = data.len().min(5000);
+            got.extend_from_slice(&data[..n]);
+            reader.consume(n);
+        }
+        assert_eq!(&got[..], &expected[100..]);
+
+        // dropped while the threads still have data to send
+        let stripe = StripeConfig::new(4096, 4);
+        let mut reader =
+            ParallelSequentialReader::new("test_data/test_data.txt", 0, None, stripe, 4096, 1).unwrap();
+        let mut buf = [0u8; 10];
+        assert_eq!(reader.read2buf(&mut buf).unwrap(), 10);
+        drop(reader);
+
+        assert!(StripeConfig::new(100, 2).check().is_err());
+        let mut config = ReaderConfig::new(4096, 2);
+        config.follow = Some(std::time::Duration::from_millis(10));
+        let stripe = StripeConfig::new(4096, 2);
+        assert!(ParallelSequentialReader::with_config("test_data/test_data.txt", 0, None, stripe, config).is_err());
+    }
+
+    #[test]
+    fn test_parallel_writer() {
+        use crate::{Durability, ParallelSequentialWriter, StripeConfig, WriterConfig};
+
+        let data = fs::read("test_data/test_data.txt").unwrap();
+        let fpath = "test_data/parallel_writer.bin";
+        for (stripe_size, num_threads, chunk) in [(4096, 3, 1000), (8192, 2, 5000), (1 << 20, 4, 1 << 16)] {
+            let _ = fs::remove_file(fpath);
+            let stripe = StripeConfig::new(stripe_size, num_threads);
+            let mut writer = ParallelSequentialWriter::new(fpath, 0, stripe, 4096, 2).unwrap();
+            for part in data.chunks(chunk) {
+                writer.write(part).unwrap();
+            }
+            writer.finish().unwrap();
+            assert_eq!(fs::read(fpath).unwrap(), data);
+        }
+
+        // from an aligned position of a longer file, the padding of the tail must not hit the old data
+        fs::write(fpath, vec![b'x'; data.len() + 10000]).unwrap();
+        let mut config = WriterConfig::new(8192, 2);
+        config.durability = Durability::OnFinish;
+        let stripe = StripeConfig::new(4096, 2);
+        let mut writer = ParallelSequentialWriter::with_config(fpath, 8192, stripe, config).unwrap();
+        writer.write(&data[..data.len() - 1]).unwrap();
+        drop(writer);
+        let out = fs::read(fpath).unwrap();
+        assert_eq!(out.len(), data.len() + 10000);
+        assert!(out[..8192].iter().all(|&b| b == b'x'));
+        assert_eq!(&out[8192..8192 + data.len() - 1], &data[..data.len() - 1]);
+        assert!(out[8192 + data.len() - 1..].iter().all(|&b| b == b'x'));
+
+        assert!(ParallelSequentialWriter::new(fpath, 100, stripe, 4096, 2).is_err());
+        fs::remove_file(fpath).unwrap();
+    }
+
+    #[test]
+    fn test_split_ranges() {
+        use crate::{FrameFormat, LengthPrefix, Records, split_ranges};
+
+        let data = fs::read("test_data/test_data.txt").unwrap();
+        let size = data.len() as u64;
+        for n in [1, 2, 3, 7, 100, 100_000] {
+            let ranges = split_ranges("test_data/test_data.txt", n, Records::Delimiter(b'\n')).unwrap();
+            assert!(!ranges.is_empty() && ranges.len() <= n);
+            assert_eq!(ranges[0].start, 0);
+            assert_eq!(ranges.last().unwrap().end, size);
+            for w in ranges.windows(2) {
+                assert_eq!(w[0].end, w[1].start);
+                assert_eq!(data[w[1].start as usize - 1], b'\n');
+            }
+        }
+        let lines = data.split(|&b| b == b'\n').count();
+        let ranges = split_ranges("test_data/test_data.txt", 4, Records::Delimiter(b'\n')).unwrap();
+        let mut total = 0;
+        for range in ranges {
+            let mut reader =
+                SequentialReader::new("test_data/test_data.txt", range.start, 4096, 2, Some(range.end)).unwrap();
+            let part = read_to_end(&mut reader).unwrap();
+            assert_eq!(part, &data[range.start as usize..range.end as usize]);
+            total += part.split(|&b| b == b'\n').filter(|l| !l.is_empty()).count();
+        }
+        assert!(total == lines || total + 1 == lines);
+
+        let fpath = "test_data/split_frames.bin";
+        for format in [
+            FrameFormat::new(LengthPrefix::U32Le),
+            FrameFormat::new(LengthPrefix::U32Be).with_crc32c(),
+            FrameFormat::new(LengthPrefix::Varint),
+        ] {
+            let mut out = vec![];
+            let mut starts = vec![];
+            for i in 0..500u32 {
+                starts.push(out.len() as u64);
+                let len = (i * 37) % 300;
+                match format.prefix {
+                    LengthPrefix::U32Le => out.extend_from_slice(&len.to_le_bytes()),
+                    LengthPrefix::U32Be => out.extend_from_slice(&len.to_be_bytes()),
+                    LengthPrefix::Varint if len < 0x80 => out.push(len as u8),
+                    LengthPrefix::Varint => out.extend_from_slice(&[len as u8 | 0x80, (len >> 7) as u8]),
+                }
+                out.extend(std::iter::repeat_n(i as u8, len as usize + format.trailer_len()));
+            }
+            fs::write(fpath, &out).unwrap();
+            let ranges = split_ranges(fpath, 5, format).unwrap();
+            assert_eq!(ranges.len(), 5);
+            assert_eq!(ranges.last().unwrap().end, out.len() as u64);
+            for range in &ranges {
+                assert!(starts.contains(&range.start));
+            }
+        }
+        // the frame at the start claims more bytes than the file has
+        let mut out = 1000u32.to_le_bytes().to_vec();
+        out.extend_from_slice(&[0; 100]);
+        fs::write(fpath, &out).unwrap();
+        assert!(split_ranges(fpath, 2, FrameFormat::new(LengthPrefix::U32Le)).is_err());
+        // corrupt varints whose length overflows the frame length or the position after it
+        let format = FrameFormat::new(LengthPrefix::Varint);
+        for (first, len) in [(0, u64::MAX), (19, u64::MAX - 20)] {
+            let mut out = vec![];
+            format.encode_header(first, &mut out).unwrap();
+            out.extend(std::iter::repeat_n(0, first as usize));
+            format.encode_header(len, &mut out).unwrap();
+            out.extend_from_slice(&[0; 100]);
+            fs::write(fpath, &out).unwrap();
+            assert!(split_ranges(fpath, 2, format).is_err());
+        }
+        fs::remove_file(fpath).unwrap();
+    }
+
+    #[test]
+    fn test_par_chunks() {
+        use crate::{ChunkOptions, FrameFormat, LengthPrefix, Records, par_chunks};
+
+        let data = fs::read("test_data/test_data.txt").unwrap();
+        let fpath = "test_data/test_data.txt";
+
+        let sum = par_chunks(fpath, ChunkOptions::new(1000, 3))
+            .map(|chunk| chunk.data.iter().map(|&b| b as u64).sum::<u64>())
+            .reduce(0, |acc, s| acc + s)
+            .unwrap();
+        assert_eq!(sum, data.iter().map(|&b| b as u64).sum::<u64>());
+
+        // in order, with the offsets of the chunks
+        let chunks = par_chunks(fpath, ChunkOptions::new(4096, 4))
+            .map_collect(|chunk| (chunk.index, chunk.offset, chunk.data.to_vec()))
+            .unwrap();
+        let mut offset = 0;
+        for (i, (index, chunk_offset, part)) in chunks.iter().enumerate() {
+            assert_eq!((*index, *chunk_offset), (i, offset));
+            offset += part.len() as u64;
+        }
+        assert_eq!(chunks.into_iter().flat_map(|c| c.2).collect::<Vec<_>>(), data);
+
+        // record aligned, chunks smaller than some lines
+        let options = ChunkOptions::new(16, 2).records(Records::Delimiter(b'\n'));
+        let lines = par_chunks(fpath, options)
+            .map(|chunk| {
+                assert!(chunk.data.ends_with(b"\n") || chunk.offset + chunk.data.len() as u64 == data.len() as u64);
+                chunk.data.split(|&b| b == b'\n').filter(|l| !l.is_empty()).count()
+            })
+            .reduce(0, |acc, n| acc + n)
+            .unwrap();
+        assert_eq!(lines, data.split(|&b| b == b'\n').filter(|l| !l.is_empty()).count());
+
+        let frames_path = "test_data/par_chunks_frames.bin";
+        let mut frames = vec![];
+        for i in 0..200u32 {
+            frames.extend_from_slice(&(i % 50).to_le_bytes());
+            frames.extend(std::iter::repeat_n(i as u8, (i % 50) as usize));
+        }
+        fs::write(frames_path, &frames).unwrap();
+        let options = ChunkOptions::new(64, 3).records(FrameFormat::new(LengthPrefix::U32Le));
+        let counts = par_chunks(frames_path, options)
+            .map_collect(|chunk| {
+                let mut pos = 0;
+                let mut n = 0;
+                while pos < chunk.data.len() {
+                    pos += 4 + u32::from_le_bytes(chunk.data[pos..pos + 4].try_into().unwrap()) as usize;
+                    n += 1;
+                }
+                assert_eq!(pos, chunk.data.len());
+                n
+            })
+            .unwrap();
+        assert_eq!(counts.iter().sum::<usize>(), 200);
+        // corrupt varints whose length overflows the frame length or the position after it
+        let format = FrameFormat::new(LengthPrefix::Varint);
+        for (first, len) in [(0, u64::MAX), (19, u64::MAX - 20)] {
+            let mut frames = vec![];
+            format.encode_header(first, &mut frames).unwrap();
+            frames.extend(std::iter::repeat_n(0, first as usize));
+            format.encode_header(len, &mut frames).unwrap();
+            frames.extend_from_slice(&[0; 100]);
+            fs::write(frames_path, &frames).unwrap();
+            let options = ChunkOptions::new(64, 2).records(format);
+            assert!(par_chunks(frames_path, options).map_collect(|_| ()).is_err());
+        }
+        fs::remove_file(frames_path).unwrap();
+
+        let failed = par_chunks(fpath, ChunkOptions::new(1000, 2)).map_collect(|chunk| {
+            if chunk.index == 3 {
+                panic!("map failed");
+            }
+        });
+        assert!(failed.is_err());
+        assert!(par_chunks("test_data/missing.txt", ChunkOptions::new(1000, 2)).map_collect(|_| ()).is_err());
+    }
+
+    #[test]
+    fn test_reader_lines() {
+        use crate::ReaderConfig;
+
+        let data = fs::read("test_data/test_data.txt").unwrap();
+        let mut want = data.split(|&b| b == b'\n').collect::<Vec<_>>();
+        if data.last() == Some(&b'\n') {
+            want.pop();
+        }
+        for provided_buffers in [false, true] {
+            let mut config = ReaderConfig::new(4096, 3);
+            config.provided_buffers = provided_buffers;
+            let mut reader = SequentialReader::with_config("test_data/test_data.txt", 0, None, config).unwrap();
+            let mut lines = reader.lines();
+            let mut got = vec![];
+            while let Some(line) = lines.next_line().unwrap() {
+                got.push(line.to_vec());
+            }
+            assert_eq!(got, want);
+        }
+
+        // records of another delimiter cross the buffers, they come back whole
+        let mut reader = SequentialReader::new("test_data/test_data.txt", 0, 4096, 2, None).unwrap();
+        let mut out = vec![];
+        while let Some(record) = reader.read_until(b' ', None).unwrap() {
+            assert!(record.ends_with(b" ") || out.len() + record.len() == data.len());
+            out.extend_from_slice(record);
+        }
+        assert_eq!(out, data);
+
+        let fpath = "test_data/crlf_lines.txt";
+        let mut crlf = b"first\r\n\r\nthird\nlast\r".to_vec();
+        crlf.extend(std::iter::repeat_n(b'x', 5000));
+        crlf.extend_from_slice(b"\r\nend");
+        fs::write(fpath, &crlf).unwrap();
+        let mut reader = SequentialReader::new(fpath, 0, 4096, 2, None).unwrap();
+        let mut lines = reader.lines();
+        assert_eq!(lines.next_line().unwrap(), Some(&b"first"[..]));
+        assert_eq!(lines.next_line().unwrap(), Some(&b""[..]));
+        assert_eq!(lines.next_line().unwrap(), Some(&b"third"[..]));
+        assert_eq!(lines.next_line().unwrap().unwrap().len(), 5005);
+        assert_eq!(lines.next_line().unwrap(), Some(&b"end"[..]));
+        assert_eq!(lines.next_line().unwrap(), None);
+
+        let mut reader = SequentialReader::new(fpath, 0, 4096, 2, None).unwrap();
+        let mut lines = reader.lines().max_len(5005);
+        for _ in 0..4 {
+            lines.next_line().unwrap();
+        }
+        let mut reader = SequentialReader::new(fpath, 0, 4096, 2, None).unwrap();
+        let mut lines = reader.lines().max_len(5004);
+        for _ in 0..3 {
+            lines.next_line().unwrap();
+        }
+        let err = lines.next_line().unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidData));
+        fs::remove_file(fpath).unwrap();
+    }
+
+    #[test]
+    fn test_frames() {
+        use crate::{FrameFormat, FrameReader, FrameWriter, LengthPrefix, linux::frame::crc32c};
+
+        // the check value of CRC-32C
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+
+        let fpath = "test_data/frames.bin";
+        let payloads = (0..300u32)
+            .map(|i| vec![i as u8; ((i * 97) % 9000) as usize])
+            .collect::<Vec<_>>();
+        for format in [
+            FrameFormat::new(LengthPrefix::U32Le),
+            FrameFormat::new(LengthPrefix::U32Be).with_crc32c(),
+            FrameFormat::new(LengthPrefix::Varint).with_crc32c(),
+        ] {
+            let _ = fs::remove_file(fpath);
+            let mut writer = FrameWriter::new(SequentialWriter::new(fpath, 0, 4096, 2).unwrap(), format);
+            for payload in &payloads {
+                writer.write_frame(payload).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let reader = SequentialReader::new(fpath, 0, 4096, 3, None).unwrap();
+            let mut reader = FrameReader::new(reader, format);
+            let mut got = vec![];
+            while let Some(payload) = reader.next_frame().unwrap() {
+                got.push(payload.to_vec());
+            }
+            assert_eq!(got, payloads);
+        }
+
+        // corrupted payload, oversized and cut off frames
+        let format = FrameFormat::new(LengthPrefix::U32Le).with_crc32c();
+        let mut data = fs::read(fpath).unwrap();
+        let _ = fs::remove_file(fpath);
+        let mut writer = FrameWriter::new(SequentialWriter::new(fpath, 0, 4096, 2).unwrap(), format).max_frame_size(10);
+        writer.write_frame(b"0123456789").unwrap();
+        assert!(writer.write_frame(b"0123456789a").is_err());
+        writer.write_frame(b"abc").unwrap();
+        writer.finish().unwrap();
+        let mut bytes = fs::read(fpath).unwrap();
+        bytes[5] ^= 1;
+        fs::write(fpath, &bytes).unwrap();
+        let mut reader = FrameReader::new(SequentialReader::new(fpath, 0, 4096, 2, None).unwrap(), format);
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidData));
+        // the reader is fused after an error
+        assert!(reader.next_frame().is_err());
+
+        fs::write(fpath, &bytes[18..]).unwrap();
+        let mut reader =
+            FrameReader::new(SequentialReader::new(fpath, 0, 4096, 2, None).unwrap(), format).max_frame_size(2);
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidData));
+        assert!(reader.next_frame().is_err());
+
+        // the same across buffers
+        let _ = fs::remove_file(fpath);
+        let mut writer = FrameWriter::new(SequentialWriter::new(fpath, 0, 4096, 2).unwrap(), format);
+        writer.write_frame(&[b'x'; 4000]).unwrap();
+        writer.write_frame(&[b'y'; 5000]).unwrap();
+        writer.write_frame(b"abc").unwrap();
+        writer.finish().unwrap();
+        let mut bytes = fs::read(fpath).unwrap();
+        bytes[6000] ^= 1;
+        fs::write(fpath, &bytes).unwrap();
+        let mut reader = FrameReader::new(SequentialReader::new(fpath, 0, 4096, 2, None).unwrap(), format);
+        assert_eq!(reader.next_frame().unwrap().unwrap(), &[b'x'; 4000]);
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidData));
+        assert!(reader.next_frame().is_err());
+        let mut reader =
+            FrameReader::new(SequentialReader::new(fpath, 0, 4096, 2, None).unwrap(), format).max_frame_size(4000);
+        assert_eq!(reader.next_frame().unwrap().unwrap(), &[b'x'; 4000]);
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidData));
+        assert!(reader.next_frame().is_err());
+
+        data.truncate(data.len() - 3);
+        fs::write(fpath, &data).unwrap();
+        let format = FrameFormat::new(LengthPrefix::Varint).with_crc32c();
+        let mut reader = FrameReader::new(SequentialReader::new(fpath, 0, 4096, 2, None).unwrap(), format);
+        let err = loop {
+            if let Err(err) = reader.next_frame() {
+                break err;
+            }
+        };
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::UnexpectedEof));
+
+        // a corrupt varint of u64::MAX overflows the frame length
+        let mut bytes = vec![0xff; 9];
+        bytes.push(0x01);
+        bytes.extend_from_slice(&[0; 100]);
+        fs::write(fpath, &bytes).unwrap();
+        let mut reader = FrameReader::new(SequentialReader::new(fpath, 0, 4096, 2, None).unwrap(), format);
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidData));
+        assert!(reader.next_frame().is_err());
+        // a huge length runs into the end instead of being allocated
+        let format = FrameFormat::new(LengthPrefix::U32Le);
+        let mut bytes = 0xffff_fff0_u32.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0; 100]);
+        fs::write(fpath, &bytes).unwrap();
+        let mut reader = FrameReader::new(SequentialReader::new(fpath, 0, 4096, 2, None).unwrap(), format);
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::UnexpectedEof));
+        fs::remove_file(fpath).unwrap();
+    }
 }
@@ -0,0 +1,339 @@
+#![cfg(all(target_os = "linux", any(feature = "tokio", feature = "futures")))]
+use std::{
+    collections::HashMap,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    pin::Pin,
+    sync::{Mutex, OnceLock},
+    task::{Context, Poll, Waker},
+};
+
+use super::{
+    sequential_reader::SequentialReader, sequential_writer::SequentialWriter, utils::would_block,
+};
+
+/// one thread for the whole process that waits on the eventfds of the async streams with epoll
+/// and wakes the tasks waiting for them, so that no runtime worker ever blocks on a ring.
+struct Watcher {
+    epfd: OwnedFd,
+    wakers: Mutex<HashMap<RawFd, Vec<Waker>>>, // streams of a shared `IoContext` have the same eventfd
+}
+
+static WATCHER: OnceLock<io::Result<Watcher>> = OnceLock::new();
+
+impl Watcher {
+    fn get() -> io::Result<&'static Watcher> {
+        WATCHER
+            .get_or_init(|| {
+                let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+                if epfd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                std::thread::Builder::new()
+                    .name("fiox-async-watcher".to_string())
+                    .spawn(|| {
+                        if let Ok(watcher) = WATCHER.wait() {
+                            watcher.run()
+                        }
+                    })?;
+                Ok(Watcher {
+                    epfd: unsafe { OwnedFd::from_raw_fd(epfd) },
+                    wakers: Mutex::new(HashMap::new()),
+                })
+            })
+            .as_ref()
+            .map_err(|e| io::Error::new(e.kind(), e.to_string()))
+    }
+
+    fn run(&self) {
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 64];
+        loop {
+            let n = unsafe {
+                libc::epoll_wait(
+                    self.epfd.as_raw_fd(),
+                    events.as_mut_ptr(),
+                    events.len() as i32,
+                    -1,
+                )
+            };
+            if n < 0 {
+                // EINTR, nothing else is expected from a valid epoll fd
+                continue;
+            }
+            let mut wakers = self.wakers.lock().unwrap_or_else(|e| e.into_inner());
+            for event in &events[..n as usize] {
+                for waker in wakers.remove(&(event.u64 as RawFd)).unwrap_or_default() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    /// wake `waker` once `fd` is readable. one shot, the fd is re-armed on every call
+    fn arm(&self, fd: RawFd, waker: &Waker) -> io::Result<()> {
+        let mut wakers = self.wakers.lock().unwrap_or_else(|e| e.into_inner());
+        let list = wakers.entry(fd).or_default();
+        if !list.iter().any(|w| w.will_wake(waker)) {
+            list.push(waker.clone());
+        }
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLONESHOT) as u32,
+            u64: fd as u64,
+        };
+        let epfd = self.epfd.as_raw_fd();
+        // closed eventfds leave the epoll set on their own, so an fd may be new again
+        if unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_MOD, fd, &mut event) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ENOENT) {
+            return Err(err);
+        }
+        if unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut event) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// drop the wakers of `fd` and take it out of the epoll set. the other streams sharing it
+    /// are woken, they arm it again on their next poll
+    fn forget(&self, fd: RawFd) {
+        let mut wakers = self.wakers.lock().unwrap_or_else(|e| e.into_inner());
+        let list = wakers.remove(&fd).unwrap_or_default();
+        unsafe {
+            libc::epoll_ctl(
+                self.epfd.as_raw_fd(),
+                libc::EPOLL_CTL_DEL,
+                fd,
+                std::ptr::null_mut(),
+            )
+        };
+        drop(wakers);
+        for waker in list {
+            waker.wake();
+        }
+    }
+}
+
+/// the eventfd of an async stream, forgotten by the watcher when the stream goes away.
+/// declared before the stream so it is dropped while the fd is still open
+struct Watched(RawFd);
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        // without a watcher nothing was armed
+        if let Some(Ok(watcher)) = WATCHER.get() {
+            watcher.forget(self.0);
+        }
+    }
+}
+
+fn into_io_error(err: anyhow::Error) -> io::Error {
+    match err.downcast::<io::Error>() {
+        Ok(e) => e,
+        Err(err) => io::Error::other(err),
+    }
+}
+
+/// run `op`, registering the task for the eventfd when it would block
+fn poll_op<T>(
+    cx: &mut Context<'_>,
+    eventfd: RawFd,
+    mut op: impl FnMut() -> anyhow::Result<T>,
+) -> Poll<io::Result<T>> {
+    match op() {
+        Ok(v) => return Poll::Ready(Ok(v)),
+        Err(e) if would_block(&e) => {}
+        Err(e) => return Poll::Ready(Err(into_io_error(e))),
+    }
+    if let Err(e) = Watcher::get().and_then(|watcher| watcher.arm(eventfd, cx.waker())) {
+        return Poll::Ready(Err(e));
+    }
+    // a completion that arrived before the fd was armed is picked up here,
+    // the wake up it triggers is then spurious
+    match op() {
+        Ok(v) => Poll::Ready(Ok(v)),
+        Err(e) if would_block(&e) => Poll::Pending,
+        Err(e) => Poll::Ready(Err(into_io_error(e))),
+    }
+}
+
+/// `SequentialReader` as `AsyncRead` + `AsyncBufRead` for tokio (feature `tokio`)
+/// and futures (feature `futures`). tasks are woken through the ring's eventfd.
+pub struct AsyncSequentialReader {
+    eventfd: Watched,
+    reader: SequentialReader,
+    pending_err: Option<io::Error>, // of `consume`, returned by the next poll
+}
+
+impl AsyncSequentialReader {
+    pub fn new(mut reader: SequentialReader) -> anyhow::Result<Self> {
+        let eventfd = Watched(reader.eventfd()?);
+        Ok(Self {
+            eventfd,
+            reader,
+            pending_err: None,
+        })
+    }
+
+    pub fn into_inner(self) -> SequentialReader {
+        self.reader
+    }
+
+    fn poll_read_slice(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if let Some(e) = self.pending_err.take() {
+            return Poll::Ready(Err(e));
+        }
+        let Self {
+            reader, eventfd, ..
+        } = self;
+        poll_op(cx, eventfd.0, || reader.try_read(buf))
+    }
+
+    fn poll_fill_buf_inner(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        if let Some(e) = self.pending_err.take() {
+            return Poll::Ready(Err(e));
+        }
+        let Self {
+            reader, eventfd, ..
+        } = self;
+        match poll_op(cx, eventfd.0, || reader.try_fill_buf().map(|_| ())) {
+            // the data is ready now, borrow it outside of the closure
+            Poll::Ready(Ok(())) => Poll::Ready(reader.try_fill_buf().map_err(into_io_error)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn consume_inner(&mut self, amt: usize) {
+        if let Err(e) = self.reader.consume(amt) {
+            // the read of the next buffer could not be submitted, `consume` can not fail
+            self.pending_err = Some(into_io_error(e));
+        }
+    }
+}
+
+/// `SequentialWriter` as `AsyncWrite` for tokio (feature `tokio`) and futures (feature `futures`).
+/// flush waits for the submitted buffers and writes the unaligned tail, see `try_flush_tail`,
+/// shutdown also finishes the writer, see `try_finish`.
+pub struct AsyncSequentialWriter {
+    eventfd: Watched,
+    writer: SequentialWriter,
+}
+
+impl AsyncSequentialWriter {
+    pub fn new(mut writer: SequentialWriter) -> anyhow::Result<Self> {
+        let eventfd = Watched(writer.eventfd()?);
+        Ok(Self { eventfd, writer })
+    }
+
+    pub fn into_inner(self) -> SequentialWriter {
+        self.writer
+    }
+
+    fn poll_write_slice(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let Self { writer, eventfd } = self;
+        poll_op(cx, eventfd.0, || writer.try_write(buf))
+    }
+
+    fn poll_flush_inner(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { writer, eventfd } = self;
+        poll_op(cx, eventfd.0, || writer.try_flush_tail())
+    }
+
+    fn poll_shutdown_inner(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { writer, eventfd } = self;
+        poll_op(cx, eventfd.0, || writer.try_finish())
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod tokio_io {
+    use super::*;
+    use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+    impl AsyncRead for AsyncSequentialReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            let n = std::task::ready!(this.poll_read_slice(cx, buf.initialize_unfilled()))?;
+            buf.advance(n);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncBufRead for AsyncSequentialReader {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            self.get_mut().poll_fill_buf_inner(cx)
+        }
+
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            self.get_mut().consume_inner(amt)
+        }
+    }
+
+    impl AsyncWrite for AsyncSequentialWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.get_mut().poll_write_slice(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.get_mut().poll_flush_inner(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.get_mut().poll_shutdown_inner(cx)
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+mod futures_io_impl {
+    use super::*;
+    use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+    impl AsyncRead for AsyncSequentialReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            self.get_mut().poll_read_slice(cx, buf)
+        }
+    }
+
+    impl AsyncBufRead for AsyncSequentialReader {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            self.get_mut().poll_fill_buf_inner(cx)
+        }
+
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            self.get_mut().consume_inner(amt)
+        }
+    }
+
+    impl AsyncWrite for AsyncSequentialWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.get_mut().poll_write_slice(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.get_mut().poll_flush_inner(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.get_mut().poll_shutdown_inner(cx)
+        }
+    }
+}
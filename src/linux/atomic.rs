@@ -0,0 +1,176 @@
+#![cfg(target_os = "linux")]
+//! files that only appear under their final name once they are complete
+
+use std::{
+    ffi::CString,
+    fs::{self, OpenOptions},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::Context;
+
+use super::lock::{self, LockMode};
+
+/// where the data of an atomic file lives until it is published
+pub(crate) enum TempFile {
+    /// an unnamed `O_TMPFILE`, gone when closed
+    Unnamed,
+    /// a hidden file next to the final one, removed unless published
+    Named(PathBuf),
+}
+
+/// a file being written for `final_path`
+pub(crate) struct AtomicTarget {
+    pub final_path: PathBuf,
+    pub temp: TempFile,
+    /// the locked lock file of `final_path`, released when the target is dropped
+    #[allow(unused)]
+    lock: Option<fs::File>,
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// a hidden name in the directory of `final_path` that is not used yet
+fn temp_name(final_path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let name = final_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    parent_dir(final_path).join(format!(
+        ".{}.tmp-{}-{}",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// take an exclusive `flock` on the hidden lock file of `final_path`. the temp file is a new file
+/// for every writer and the final path gets a new file on every commit, so neither can carry the lock.
+/// the lock file is left in place, removing it would let two writers lock different files
+fn lock_final(final_path: &Path, mode: LockMode) -> anyhow::Result<Option<fs::File>> {
+    if mode == LockMode::None {
+        return Ok(None);
+    }
+    let name = final_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let lock_path = parent_dir(final_path).join(format!(".{}.lock", name));
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o644)
+        .open(&lock_path)
+        .with_context(|| format!("open {:?} failed", lock_path))?;
+    lock::lock(&file, &final_path.to_string_lossy(), true, mode)?;
+    Ok(Some(file))
+}
+
+/// open a temp file with O_DIRECT in the directory of `final_path`, an `O_TMPFILE` if the
+/// file system supports it and a hidden named one otherwise. with a `lock` the final path
+/// is locked until the target is dropped
+pub(crate) fn create(
+    final_path: &str,
+    lock: LockMode,
+) -> anyhow::Result<(fs::File, AtomicTarget)> {
+    let final_path = PathBuf::from(final_path);
+    let lock = lock_final(&final_path, lock)?;
+    let dir = parent_dir(&final_path);
+    match OpenOptions::new()
+        .write(true)
+        .mode(0o644)
+        .custom_flags(libc::O_TMPFILE | libc::O_DIRECT)
+        .open(dir)
+    {
+        Ok(file) => {
+            return Ok((
+                file,
+                AtomicTarget {
+                    final_path,
+                    temp: TempFile::Unnamed,
+                    lock,
+                },
+            ));
+        }
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::EOPNOTSUPP) | Some(libc::EISDIR) | Some(libc::EINVAL)
+            ) => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("open temp file in {:?} failed", dir));
+        }
+    }
+
+    let temp_path = temp_name(&final_path);
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(&temp_path)
+        .with_context(|| format!("open {:?} failed", temp_path))?;
+    Ok((
+        file,
+        AtomicTarget {
+            final_path,
+            temp: TempFile::Named(temp_path),
+            lock,
+        },
+    ))
+}
+
+impl AtomicTarget {
+    /// move the synced `file` to the final path, replacing what is there, and sync the directory
+    pub fn publish(&mut self, file: &fs::File) -> anyhow::Result<()> {
+        let temp_path = match &self.temp {
+            TempFile::Named(temp_path) => temp_path.clone(),
+            TempFile::Unnamed => {
+                // linkat can not replace a file, the name it creates is renamed over the final one
+                let temp_path = temp_name(&self.final_path);
+                let proc_path = CString::new(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+                let target = CString::new(temp_path.to_string_lossy().into_owned())?;
+                let ret = unsafe {
+                    libc::linkat(
+                        libc::AT_FDCWD,
+                        proc_path.as_ptr(),
+                        libc::AT_FDCWD,
+                        target.as_ptr(),
+                        libc::AT_SYMLINK_FOLLOW,
+                    )
+                };
+                if ret != 0 {
+                    return Err(std::io::Error::last_os_error())
+                        .with_context(|| format!("link {:?} failed", temp_path));
+                }
+                self.temp = TempFile::Named(temp_path.clone());
+                temp_path
+            }
+        };
+        fs::rename(&temp_path, &self.final_path)
+            .with_context(|| format!("rename {:?} to {:?} failed", temp_path, self.final_path))?;
+        self.temp = TempFile::Unnamed;
+
+        let dir = parent_dir(&self.final_path);
+        fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("fsync {:?} failed", dir))?;
+        Ok(())
+    }
+
+    /// drop the data, nothing appears under the final path
+    pub fn discard(&mut self) {
+        if let TempFile::Named(temp_path) = &self.temp {
+            let _ = fs::remove_file(temp_path);
+        }
+        self.temp = TempFile::Unnamed;
+    }
+}
@@ -0,0 +1,66 @@
+#![cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use io_uring::types::BufRingEntry;
+
+use super::{
+    buffer::{AlignedVecU8, AllocOptions},
+    utils::get_page_size,
+};
+
+/// the memory of an io_uring provided buffer ring (`IORING_REGISTER_PBUF_RING`).
+///
+/// the application adds free buffers at the tail, the kernel takes one for every
+/// `BUFFER_SELECT` request and reports its id in the completion flags.
+pub struct ProvidedBufRing {
+    mem: AlignedVecU8,
+    entries: u16,
+    tail: u16,
+}
+
+impl ProvidedBufRing {
+    /// room for at least `n` buffers, the kernel wants a power of two
+    pub fn new(n: usize) -> anyhow::Result<Self> {
+        let entries = n.next_power_of_two();
+        if entries > 32768 {
+            anyhow::bail!("{} buffers exceed the provided buffer ring limit 32768", n);
+        }
+        let size = entries * std::mem::size_of::<BufRingEntry>();
+        let page_size = get_page_size();
+        let mut mem = AlignedVecU8::with_options(
+            size.div_ceil(page_size) * page_size,
+            page_size,
+            AllocOptions::default(),
+        )?;
+        mem.fill(0);
+        Ok(Self {
+            mem,
+            entries: entries as u16,
+            tail: 0,
+        })
+    }
+
+    pub fn addr(&self) -> u64 {
+        self.mem.as_ptr() as u64
+    }
+
+    pub fn entries(&self) -> u16 {
+        self.entries
+    }
+
+    /// hand buffer `bid` back to the kernel
+    pub fn push(&mut self, addr: *mut u8, len: u32, bid: u16) {
+        let base = self.mem.as_mut_ptr() as *mut BufRingEntry;
+        let idx = (self.tail & (self.entries - 1)) as usize;
+        unsafe {
+            let entry = &mut *base.add(idx);
+            entry.set_addr(addr as u64);
+            entry.set_len(len);
+            entry.set_bid(bid);
+            self.tail = self.tail.wrapping_add(1);
+            // the entry must be visible before the new tail
+            let tail = BufRingEntry::tail(base) as *const AtomicU16;
+            (*tail).store(self.tail, Ordering::Release);
+        }
+    }
+}
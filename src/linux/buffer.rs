@@ -1,6 +1,11 @@
 #![cfg(target_os = "linux")]
 
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use super::buffer_pool::PoolInner;
 
 pub fn aligned_alloc(size: usize, page_size: usize) -> Vec<u8> {
     use std::ptr;
@@ -14,41 +19,234 @@ pub fn aligned_alloc(size: usize, page_size: usize) -> Vec<u8> {
     }
 }
 
+/// size of a huge page on x86_64 and aarch64 (4 KiB base pages)
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HugePages {
+    #[default]
+    None,
+    /// align to `HUGE_PAGE_SIZE` and `madvise(MADV_HUGEPAGE)`, the kernel backs it with huge pages when it can
+    Transparent,
+    /// `mmap(MAP_HUGETLB)` from the reserved huge page pool (`vm.nr_hugepages`),
+    /// falls back to `Transparent` when no huge page is available
+    Explicit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocOptions {
+    pub huge_pages: HugePages,
+    /// `mlock` the buffer so that it is never swapped out
+    pub mlock: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backing {
+    Malloc,
+    /// mapped length, rounded up to `HUGE_PAGE_SIZE`
+    HugeTlb(usize),
+}
+
+/// page aligned memory, freed with the allocator it comes from
+/// or given back to the `BufferPool` it is borrowed from
 pub struct AlignedVecU8 {
-    vec: Vec<u8>,
+    ptr: *mut u8,
+    len: usize,
+    backing: Backing,
+    locked: bool,
+    pub(crate) pool_slot: Option<PoolSlot>,
+}
+
+// the memory is owned exclusively, like a `Vec<u8>`
+unsafe impl Send for AlignedVecU8 {}
+unsafe impl Sync for AlignedVecU8 {}
+
+impl Default for AlignedVecU8 {
+    fn default() -> Self {
+        Self {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+            backing: Backing::Malloc,
+            locked: false,
+            pool_slot: None,
+        }
+    }
 }
 
 impl AlignedVecU8 {
+    /// panics if the memory can not be allocated, `with_options` fails instead
     pub fn new(buf_size: usize, page_size: usize) -> Self {
-        let vec = aligned_alloc(buf_size, page_size);
-        Self { vec }
+        Self::with_options(buf_size, page_size, AllocOptions::default())
+            .unwrap_or_else(|e| panic!("{:?}", e))
+    }
+
+    fn malloc(buf_size: usize, page_size: usize) -> anyhow::Result<Self> {
+        let mut ptr: *mut u8 = std::ptr::null_mut();
+        let ret = unsafe { libc::posix_memalign(&mut ptr as *mut _ as *mut _, page_size, buf_size) };
+        if ret != 0 {
+            return Err(std::io::Error::from_raw_os_error(ret)).map_err(|e| {
+                anyhow::anyhow!("posix_memalign of {} bytes failed: {}", buf_size, e)
+            });
+        }
+        Ok(Self {
+            ptr,
+            len: buf_size,
+            backing: Backing::Malloc,
+            locked: false,
+            pool_slot: None,
+        })
+    }
+
+    pub fn with_options(
+        buf_size: usize,
+        page_size: usize,
+        options: AllocOptions,
+    ) -> anyhow::Result<Self> {
+        let mut buf = match options.huge_pages {
+            HugePages::None => Self::malloc(buf_size, page_size)?,
+            HugePages::Transparent => Self::transparent_huge(buf_size, page_size)?,
+            HugePages::Explicit => match Self::hugetlb(buf_size) {
+                Some(buf) => buf,
+                None => Self::transparent_huge(buf_size, page_size)?,
+            },
+        };
+        if options.mlock {
+            if unsafe { libc::mlock(buf.ptr as *const _, buf.len) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .map_err(|e| anyhow::anyhow!("mlock {} bytes failed: {}", buf_size, e));
+            }
+            buf.locked = true;
+        }
+        Ok(buf)
+    }
+
+    fn transparent_huge(buf_size: usize, page_size: usize) -> anyhow::Result<Self> {
+        let buf = Self::malloc(buf_size, page_size.max(HUGE_PAGE_SIZE))?;
+        // only a hint, the buffer is usable either way
+        unsafe {
+            libc::madvise(buf.ptr as *mut _, buf_size, libc::MADV_HUGEPAGE);
+        }
+        Ok(buf)
+    }
+
+    fn hugetlb(buf_size: usize) -> Option<Self> {
+        let map_len = buf_size.div_ceil(HUGE_PAGE_SIZE) * HUGE_PAGE_SIZE;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return None;
+        }
+        Some(Self {
+            ptr: ptr as *mut u8,
+            len: buf_size,
+            backing: Backing::HugeTlb(map_len),
+            locked: false,
+            pool_slot: None,
+        })
+    }
+
+    /// true if the memory comes from the explicit huge page pool
+    pub fn is_hugetlb(&self) -> bool {
+        matches!(self.backing, Backing::HugeTlb(_))
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Drop for AlignedVecU8 {
+    fn drop(&mut self) {
+        if let Some(slot) = self.pool_slot.take() {
+            slot.pool.release(slot.idx, std::mem::take(self));
+            return;
+        }
+        if self.ptr.is_null() {
+            return;
+        }
+        unsafe {
+            if self.locked {
+                libc::munlock(self.ptr as *const _, self.len);
+            }
+            match self.backing {
+                Backing::Malloc => libc::free(self.ptr as *mut _),
+                Backing::HugeTlb(map_len) => {
+                    libc::munmap(self.ptr as *mut _, map_len);
+                }
+            }
+        }
     }
 }
 
+/// a slice and not a `Vec<u8>` as before 0.4: the memory may be mapped or pooled,
+/// it must never be freed by the global allocator
 impl Deref for AlignedVecU8 {
-    type Target = Vec<u8>;
+    type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        &self.vec
+        if self.ptr.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for AlignedVecU8 {
+    fn as_ref(&self) -> &[u8] {
+        self
     }
 }
 
 impl DerefMut for AlignedVecU8 {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.vec
+        if self.ptr.is_null() {
+            return &mut [];
+        }
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
     }
 }
 
+/// where a buffer borrowed from a `BufferPool` goes back to
+pub(crate) struct PoolSlot {
+    pub pool: Arc<PoolInner>,
+    pub idx: usize,
+}
+
 pub struct Buffer {
     pub data: AlignedVecU8,
     pub len: usize,
-    pub cap: usize
+    pub cap: usize,
 }
 
 impl Buffer {
+    /// panics if the memory can not be allocated, `with_options` fails instead
     pub fn new(buf_size: usize, page_size: usize) -> Self {
         let data = AlignedVecU8::new(buf_size, page_size);
-        Self { data, len: 0,cap: buf_size }
+        Self {
+            data,
+            len: 0,
+            cap: buf_size,
+        }
+    }
+    pub fn with_options(
+        buf_size: usize,
+        page_size: usize,
+        options: AllocOptions,
+    ) -> anyhow::Result<Self> {
+        let data = AlignedVecU8::with_options(buf_size, page_size, options)?;
+        Ok(Self {
+            data,
+            len: 0,
+            cap: buf_size,
+        })
     }
     pub fn cap(&self) -> usize {
         self.cap
@@ -56,10 +254,20 @@ impl Buffer {
     pub fn len(&self) -> usize {
         self.len
     }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// the whole capacity, for buffer registration
+    pub fn iovec(&mut self) -> libc::iovec {
+        libc::iovec {
+            iov_base: self.data.as_mut_ptr() as *mut _,
+            iov_len: self.cap,
+        }
+    }
 }
 
 impl Deref for Buffer {
-    type Target = Vec<u8>;
+    type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
         &self.data
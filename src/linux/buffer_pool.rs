@@ -0,0 +1,219 @@
+#![cfg(target_os = "linux")]
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use super::{
+    buffer::{AlignedVecU8, AllocOptions, Buffer, PoolSlot},
+    utils::get_page_size,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BufferPoolOptions {
+    /// size of every buffer, multiple of the page size
+    pub buffer_size: usize,
+    /// upper bound of the memory allocated by the pool
+    pub max_bytes: usize,
+    pub alloc: AllocOptions,
+}
+
+impl BufferPoolOptions {
+    pub fn new(buffer_size: usize, max_bytes: usize) -> Self {
+        Self {
+            buffer_size,
+            max_bytes,
+            alloc: AllocOptions::default(),
+        }
+    }
+}
+
+struct PoolState {
+    free: Vec<(usize, AlignedVecU8)>,
+    allocated: usize, // number of buffers allocated so far, also the next slot index
+}
+
+pub(crate) struct PoolInner {
+    options: BufferPoolOptions,
+    capacity: usize,
+    state: Mutex<PoolState>,
+    released: Condvar,
+}
+
+impl PoolInner {
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn release(&self, idx: usize, data: AlignedVecU8) {
+        self.lock().free.push((idx, data));
+        self.released.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// aligned buffers shared by many readers and writers, with a cap on the total memory.
+///
+/// buffers are allocated lazily up to `max_bytes` and never freed before the pool,
+/// a `Buffer` taken from the pool goes back to it when dropped.
+/// every buffer keeps its slot index for its whole life, so an `IoContext` registers it only once.
+/// `BufferPool` is a cheap handle, clones refer to the same pool.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+impl BufferPool {
+    pub fn new(options: BufferPoolOptions) -> anyhow::Result<Self> {
+        if options.buffer_size == 0 || !options.buffer_size.is_multiple_of(get_page_size()) {
+            anyhow::bail!(
+                "buffer_size {} is not a multiple of the page size {}",
+                options.buffer_size,
+                get_page_size()
+            );
+        }
+        let capacity = options.max_bytes / options.buffer_size;
+        if capacity == 0 {
+            anyhow::bail!(
+                "max_bytes {} is smaller than buffer_size {}",
+                options.max_bytes,
+                options.buffer_size
+            );
+        }
+        Ok(Self {
+            inner: Arc::new(PoolInner {
+                options,
+                capacity,
+                state: Mutex::new(PoolState {
+                    free: vec![],
+                    allocated: 0,
+                }),
+                released: Condvar::new(),
+            }),
+        })
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.inner.options.buffer_size
+    }
+
+    /// the maximum number of buffers
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    /// bytes allocated so far, borrowed or not
+    pub fn allocated_bytes(&self) -> usize {
+        self.inner.lock().allocated * self.buffer_size()
+    }
+
+    /// number of buffers that can be taken without blocking
+    pub fn available(&self) -> usize {
+        let state = self.inner.lock();
+        state.free.len() + self.inner.capacity - state.allocated
+    }
+
+    /// take `n` buffers, blocking until they are all available.
+    /// all or nothing, so streams waiting for buffers never hold some of them.
+    pub fn acquire(&self, n: usize) -> anyhow::Result<Vec<Buffer>> {
+        if n > self.inner.capacity {
+            anyhow::bail!(
+                "{} buffers requested, the pool holds at most {}",
+                n,
+                self.inner.capacity
+            );
+        }
+        let mut state = self.inner.lock();
+        while state.free.len() + self.inner.capacity - state.allocated < n {
+            state = self
+                .inner
+                .released
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        self.take(&mut state, n)
+    }
+
+    /// the slot index of `buf` if it was taken from this pool
+    pub(crate) fn slot_of(&self, buf: &Buffer) -> Option<usize> {
+        buf.data
+            .pool_slot
+            .as_ref()
+            .filter(|slot| Arc::ptr_eq(&slot.pool, &self.inner))
+            .map(|slot| slot.idx)
+    }
+
+    /// take `n` buffers if they are available right now
+    pub fn try_acquire(&self, n: usize) -> anyhow::Result<Option<Vec<Buffer>>> {
+        let mut state = self.inner.lock();
+        if state.free.len() + self.inner.capacity - state.allocated < n {
+            return Ok(None);
+        }
+        self.take(&mut state, n).map(Some)
+    }
+
+    fn take(&self, state: &mut PoolState, n: usize) -> anyhow::Result<Vec<Buffer>> {
+        let buffer_size = self.buffer_size();
+        let mut taken = Vec::with_capacity(n);
+        while taken.len() < n {
+            match state.free.pop() {
+                Some(free) => taken.push(free),
+                None => {
+                    match AlignedVecU8::with_options(
+                        buffer_size,
+                        get_page_size(),
+                        self.inner.options.alloc,
+                    ) {
+                        Ok(data) => {
+                            taken.push((state.allocated, data));
+                            state.allocated += 1;
+                        }
+                        Err(e) => {
+                            // `state` is locked, put them back directly, they have no pool slot yet
+                            state.free.extend(taken);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(taken
+            .into_iter()
+            .map(|(idx, mut data)| {
+                data.pool_slot = Some(PoolSlot {
+                    pool: self.inner.clone(),
+                    idx,
+                });
+                Buffer {
+                    data,
+                    len: 0,
+                    cap: buffer_size,
+                }
+            })
+            .collect())
+    }
+}
+
+/// the buffers of a reader or writer, from `pool` if there is one, otherwise allocated with `alloc`
+pub(crate) fn stream_buffers(
+    buffer_size: usize,
+    num_buffer: usize,
+    pool: Option<&BufferPool>,
+    alloc: AllocOptions,
+) -> anyhow::Result<Vec<Buffer>> {
+    match pool {
+        Some(pool) => {
+            if pool.buffer_size() != buffer_size {
+                anyhow::bail!(
+                    "buffer_size {} does not match the pool's {}",
+                    buffer_size,
+                    pool.buffer_size()
+                );
+            }
+            pool.acquire(num_buffer)
+        }
+        None => (0..num_buffer)
+            .map(|_| Buffer::with_options(buffer_size, get_page_size(), alloc))
+            .collect(),
+    }
+}
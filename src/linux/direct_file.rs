@@ -0,0 +1,359 @@
+#![cfg(target_os = "linux")]
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+use anyhow::Context;
+use io_uring::{opcode, squeue, types};
+
+use super::{
+    buffer::{AllocOptions, Buffer},
+    buffer_pool::BufferPool,
+    io_context::{IoContext, StreamRing},
+    ring::Completion,
+    utils::get_page_size,
+};
+
+/// a request submitted by `DirectFile`, pass it to `DirectFile::wait` or `DirectFile::poll`.
+/// the result of a dropped handle is kept until the `DirectFile` is dropped.
+#[must_use]
+#[derive(Debug)]
+pub struct OpHandle {
+    id: u32,
+}
+
+/// the result of a finished request
+pub struct OpOutput {
+    /// bytes transferred by `read_at` / `write_at`, 0 for the other requests
+    pub len: usize,
+    /// the buffer given to `read_at` / `write_at`. `len` of a read buffer is the bytes read
+    pub buf: Option<Buffer>,
+}
+
+/// the kinds of requests, for the result and the error message
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Read,
+    Write,
+    Fallocate,
+    Fsync,
+    Fdatasync,
+    SetLen,
+}
+
+impl std::fmt::Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Op::Read => "read",
+            Op::Write => "write",
+            Op::Fallocate => "fallocate",
+            Op::Fsync => "fsync",
+            Op::Fdatasync => "fdatasync",
+            Op::SetLen => "truncate",
+        };
+        f.write_str(name)
+    }
+}
+
+struct Pending {
+    op: Op,
+    pos: u64,
+    buf: Option<Buffer>,
+    result: Option<i32>,
+}
+
+/// a file opened with O_DIRECT and registered with a ring, for access patterns the streams
+/// do not cover. requests are submitted right away and finish in any order, every call returns
+/// an `OpHandle` to wait for. offsets and lengths of reads and writes must be page aligned.
+pub struct DirectFile {
+    file: fs::File,
+    fpath: String,
+    ring: StreamRing,
+    pool: Option<BufferPool>, // its buffers are registered with the ring
+    fixed: Vec<u16>,          // registered buffer index of each slot of `pool`
+    next_id: u32,
+    pending: HashMap<u32, Pending>,
+}
+
+impl DirectFile {
+    /// open an existing file for reading
+    pub fn open(fpath: &str, queue_depth: usize) -> anyhow::Result<Self> {
+        Self::with_options(fpath, OpenOptions::new().read(true), queue_depth)
+    }
+
+    /// open a file for reading and writing, it is created if missing
+    pub fn create(fpath: &str, queue_depth: usize) -> anyhow::Result<Self> {
+        Self::with_options(
+            fpath,
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false),
+            queue_depth,
+        )
+    }
+
+    /// open with `options`, O_DIRECT is added
+    pub fn with_options(
+        fpath: &str,
+        options: &mut OpenOptions,
+        queue_depth: usize,
+    ) -> anyhow::Result<Self> {
+        Self::new_in(None, fpath, options, queue_depth, None)
+    }
+
+    /// `with_options` with the buffers of `pool` registered with the ring, reads and writes
+    /// of them skip mapping the memory on every request. waits until every buffer of the pool
+    /// is free to register it, see `buffer`
+    pub fn with_pool(
+        fpath: &str,
+        options: &mut OpenOptions,
+        queue_depth: usize,
+        pool: BufferPool,
+    ) -> anyhow::Result<Self> {
+        Self::new_in(None, fpath, options, queue_depth, Some(pool))
+    }
+
+    /// `ctx`: the shared ring to submit to, a private ring is created if `None`
+    pub(crate) fn new_in(
+        ctx: Option<&IoContext>,
+        fpath: &str,
+        options: &mut OpenOptions,
+        queue_depth: usize,
+        pool: Option<BufferPool>,
+    ) -> anyhow::Result<Self> {
+        if queue_depth == 0 {
+            anyhow::bail!("queue_depth must be at least 1");
+        }
+        let file = options
+            .custom_flags(libc::O_DIRECT)
+            .open(fpath)
+            .with_context(|| format!("open {} failed", fpath))?;
+        // pool buffers keep their memory and slot while the pool lives, so they stay registered
+        let mut buffers = match pool.as_ref() {
+            Some(pool) => pool.acquire(pool.capacity())?,
+            None => vec![],
+        };
+        let ring = match ctx {
+            Some(ctx) => ctx.open_stream(Some(file.as_raw_fd()), &mut buffers)?,
+            None => StreamRing::private(queue_depth as u32, file.as_raw_fd(), &mut buffers)?,
+        };
+        let mut fixed = vec![0; buffers.len()];
+        if let Some(pool) = pool.as_ref() {
+            for (i, buf) in buffers.iter().enumerate() {
+                let slot = pool.slot_of(buf).expect("buffer of the pool");
+                fixed[slot] = ring.buf_index(i);
+            }
+        }
+        Ok(Self {
+            file,
+            fpath: fpath.to_string(),
+            ring,
+            pool,
+            fixed,
+            next_id: 0,
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn file(&self) -> &fs::File {
+        &self.file
+    }
+
+    pub fn file_size(&self) -> anyhow::Result<u64> {
+        Ok(self
+            .file
+            .metadata()
+            .with_context(|| format!("stat {} failed", self.fpath))?
+            .len())
+    }
+
+    /// a page aligned buffer of `size` bytes, rounded up to whole pages
+    pub fn alloc_buffer(size: usize) -> anyhow::Result<Buffer> {
+        let page_size = get_page_size();
+        Buffer::with_options(
+            size.div_ceil(page_size) * page_size,
+            page_size,
+            AllocOptions::default(),
+        )
+    }
+
+    /// a registered buffer of the pool given to `with_pool`, `None` if all are in use.
+    /// it goes back to the pool when dropped
+    pub fn buffer(&self) -> anyhow::Result<Option<Buffer>> {
+        let Some(pool) = self.pool.as_ref() else {
+            anyhow::bail!("{} has no registered buffers", self.fpath);
+        };
+        Ok(pool.try_acquire(1)?.and_then(|mut bufs| bufs.pop()))
+    }
+
+    /// read `buf.cap()` bytes at `offset` into `buf`
+    pub fn read_at(&mut self, mut buf: Buffer, offset: u64) -> anyhow::Result<OpHandle> {
+        self.check_aligned(offset, buf.cap())?;
+        buf.len = 0;
+        let (ptr, len) = (buf.as_mut_ptr(), buf.cap() as u32);
+        let sqe = match self.fixed_index(&buf) {
+            Some(index) => opcode::ReadFixed::new(self.ring.file(), ptr, len, index)
+                .offset(offset)
+                .build(),
+            None => opcode::Read::new(self.ring.file(), ptr, len)
+                .offset(offset)
+                .build(),
+        };
+        self.submit(Op::Read, offset, sqe, Some(buf))
+    }
+
+    /// write `buf[..buf.len()]` at `offset`
+    pub fn write_at(&mut self, mut buf: Buffer, offset: u64) -> anyhow::Result<OpHandle> {
+        self.check_aligned(offset, buf.len())?;
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len() as u32);
+        let sqe = match self.fixed_index(&buf) {
+            Some(index) => opcode::WriteFixed::new(self.ring.file(), ptr, len, index)
+                .offset(offset)
+                .build(),
+            None => opcode::Write::new(self.ring.file(), ptr, len)
+                .offset(offset)
+                .build(),
+        };
+        self.submit(Op::Write, offset, sqe, Some(buf))
+    }
+
+    /// `mode` is the `mode` of fallocate(2), 0 allocates and extends the file
+    pub fn fallocate(&mut self, offset: u64, len: u64, mode: i32) -> anyhow::Result<OpHandle> {
+        let sqe = opcode::Fallocate::new(self.ring.file(), len)
+            .offset(offset)
+            .mode(mode)
+            .build();
+        self.submit(Op::Fallocate, offset, sqe, None)
+    }
+
+    pub fn fsync(&mut self) -> anyhow::Result<OpHandle> {
+        let sqe = opcode::Fsync::new(self.ring.file()).build();
+        self.submit(Op::Fsync, 0, sqe, None)
+    }
+
+    /// fsync without the metadata that is not needed to read the data back
+    pub fn fdatasync(&mut self) -> anyhow::Result<OpHandle> {
+        let sqe = opcode::Fsync::new(self.ring.file())
+            .flags(types::FsyncFlags::DATASYNC)
+            .build();
+        self.submit(Op::Fdatasync, 0, sqe, None)
+    }
+
+    /// truncate or extend the file to `len`, needs linux 6.9
+    pub fn set_len(&mut self, len: u64) -> anyhow::Result<OpHandle> {
+        let sqe = opcode::Ftruncate::new(self.ring.file(), len).build();
+        self.submit(Op::SetLen, len, sqe, None)
+    }
+
+    /// wait until the request of `handle` is finished
+    pub fn wait(&mut self, handle: OpHandle) -> anyhow::Result<OpOutput> {
+        while !self.is_done(&handle)? {
+            let cqe = self.ring.wait()?;
+            self.complete(cqe);
+        }
+        self.take(handle)
+    }
+
+    /// the result of `handle` if it is finished, never blocks. the handle is given back otherwise
+    pub fn poll(&mut self, handle: OpHandle) -> anyhow::Result<Result<OpOutput, OpHandle>> {
+        while !self.is_done(&handle)? {
+            match self.ring.try_wait()? {
+                Some(cqe) => self.complete(cqe),
+                None => return Ok(Err(handle)),
+            }
+        }
+        self.take(handle).map(Ok)
+    }
+
+    /// wait for every request in flight, their results are kept for their handles
+    pub fn wait_all(&mut self) -> anyhow::Result<()> {
+        while self.ring.in_flight() > 0 {
+            let cqe = self.ring.wait()?;
+            self.complete(cqe);
+        }
+        Ok(())
+    }
+
+    fn check_aligned(&self, offset: u64, len: usize) -> anyhow::Result<()> {
+        let page_size = get_page_size();
+        if !offset.is_multiple_of(page_size as u64) || !len.is_multiple_of(page_size) {
+            anyhow::bail!(
+                "{}+{} of {} is not aligned to {}",
+                offset,
+                len,
+                self.fpath,
+                page_size
+            );
+        }
+        Ok(())
+    }
+
+    /// the registered buffer index of `buf` if it belongs to the pool
+    fn fixed_index(&self, buf: &Buffer) -> Option<u16> {
+        let slot = self.pool.as_ref()?.slot_of(buf)?;
+        Some(self.fixed[slot])
+    }
+
+    fn submit(
+        &mut self,
+        op: Op,
+        pos: u64,
+        sqe: squeue::Entry,
+        buf: Option<Buffer>,
+    ) -> anyhow::Result<OpHandle> {
+        let id = self.next_id;
+        // tags with the high bit set are the ring's own
+        self.next_id = (self.next_id + 1) & 0x7fff_ffff;
+        // the buffer is kept in `pending` until the request is finished
+        unsafe { self.ring.push(sqe.user_data(id as u64))? };
+        self.pending.insert(
+            id,
+            Pending {
+                op,
+                pos,
+                buf,
+                result: None,
+            },
+        );
+        Ok(OpHandle { id })
+    }
+
+    fn is_done(&self, handle: &OpHandle) -> anyhow::Result<bool> {
+        match self.pending.get(&handle.id) {
+            Some(pending) => Ok(pending.result.is_some()),
+            None => anyhow::bail!("{:?} is not a request of {}", handle, self.fpath),
+        }
+    }
+
+    fn complete(&mut self, cqe: Completion) {
+        if let Some(pending) = self.pending.get_mut(&(cqe.user_data as u32)) {
+            pending.result = Some(cqe.result);
+        }
+    }
+
+    fn take(&mut self, handle: OpHandle) -> anyhow::Result<OpOutput> {
+        let Pending {
+            op,
+            pos,
+            mut buf,
+            result,
+        } = self.pending.remove(&handle.id).unwrap();
+        // only called once `is_done`
+        let result = result.unwrap();
+        if result < 0 {
+            return Err(std::io::Error::from_raw_os_error(-result))
+                .with_context(|| format!("{} {} at {} failed", op, self.fpath, pos));
+        }
+        let len = result as usize;
+        if op == Op::Read
+            && let Some(buf) = buf.as_mut()
+        {
+            buf.len = len;
+        }
+        Ok(OpOutput { len, buf })
+    }
+}
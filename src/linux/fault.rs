@@ -0,0 +1,119 @@
+#![cfg(target_os = "linux")]
+//! scripted fault injection for the io_uring engine.
+//!
+//! a `FaultPlan` is attached to a reader or writer and rewrites the completions of its ring:
+//! requests are numbered in submission order (starting at 0, resubmissions included),
+//! and each rule targets one of those numbers.
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use super::ring::Completion;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// complete the request with `-errno`
+    Error(i32),
+    /// complete the request with at most this many bytes
+    Short(u32),
+    /// hold the completion back for the given duration
+    Delay(Duration),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FaultPlan {
+    rules: Vec<(usize, Fault)>,
+    delay_all: Option<Duration>,
+    reorder: bool,
+}
+
+impl FaultPlan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fail_nth(mut self, nth: usize, errno: i32) -> Self {
+        self.rules.push((nth, Fault::Error(errno)));
+        self
+    }
+
+    pub fn short_nth(mut self, nth: usize, len: u32) -> Self {
+        self.rules.push((nth, Fault::Short(len)));
+        self
+    }
+
+    pub fn delay_nth(mut self, nth: usize, delay: Duration) -> Self {
+        self.rules.push((nth, Fault::Delay(delay)));
+        self
+    }
+
+    /// add latency to every completion
+    pub fn delay_all(mut self, delay: Duration) -> Self {
+        self.delay_all = Some(delay);
+        self
+    }
+
+    /// wait for all requests in flight and hand their completions out in reverse order
+    pub fn reorder(mut self) -> Self {
+        self.reorder = true;
+        self
+    }
+}
+
+pub(crate) struct FaultInjector {
+    plan: FaultPlan,
+    submitted: usize,
+    seq_of: HashMap<u64, usize>,
+}
+
+impl FaultInjector {
+    pub fn new(plan: FaultPlan) -> Self {
+        Self {
+            plan,
+            submitted: 0,
+            seq_of: HashMap::new(),
+        }
+    }
+
+    pub fn reorder(&self) -> bool {
+        self.plan.reorder
+    }
+
+    pub fn on_submit(&mut self, user_data: u64) {
+        self.seq_of.insert(user_data, self.submitted);
+        self.submitted += 1;
+    }
+
+    pub fn on_complete(&mut self, reaped: VecDeque<Completion>, ready: &mut VecDeque<Completion>) {
+        let mut reaped = reaped
+            .into_iter()
+            .map(|cqe| self.apply(cqe))
+            .collect::<Vec<_>>();
+        if self.plan.reorder {
+            reaped.reverse();
+        }
+        ready.extend(reaped);
+    }
+
+    fn apply(&mut self, mut cqe: Completion) -> Completion {
+        if let Some(delay) = self.plan.delay_all {
+            std::thread::sleep(delay);
+        }
+        let Some(seq) = self.seq_of.remove(&cqe.user_data) else {
+            return cqe;
+        };
+        for (_, fault) in self.plan.rules.iter().filter(|(nth, _)| *nth == seq) {
+            match *fault {
+                Fault::Error(errno) => cqe.result = -errno,
+                Fault::Short(len) => {
+                    if cqe.result > 0 {
+                        cqe.result = cqe.result.min(len as i32);
+                    }
+                }
+                Fault::Delay(delay) => std::thread::sleep(delay),
+            }
+        }
+        cqe
+    }
+}
@@ -0,0 +1,355 @@
+#![cfg(target_os = "linux")]
+//! length-prefixed frames: the length of the payload, the payload, then its CRC32C if enabled
+
+use std::io::ErrorKind;
+
+use super::{sequential_reader::SequentialReader, sequential_writer::SequentialWriter};
+
+/// how the payload length of a frame is stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LengthPrefix {
+    U32Le,
+    U32Be,
+    /// LEB128, 7 bits per byte with the low bits first
+    Varint,
+}
+
+/// the layout of a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameFormat {
+    pub prefix: LengthPrefix,
+    /// a little endian CRC32C of the payload follows it
+    pub crc32c: bool,
+}
+
+/// the longest varint of a u64
+const MAX_VARINT_LEN: usize = 10;
+
+impl FrameFormat {
+    pub fn new(prefix: LengthPrefix) -> Self {
+        Self {
+            prefix,
+            crc32c: false,
+        }
+    }
+
+    pub fn with_crc32c(mut self) -> Self {
+        self.crc32c = true;
+        self
+    }
+
+    /// the most bytes a length prefix takes
+    pub(crate) fn max_header_len(&self) -> usize {
+        match self.prefix {
+            LengthPrefix::U32Le | LengthPrefix::U32Be => 4,
+            LengthPrefix::Varint => MAX_VARINT_LEN,
+        }
+    }
+
+    /// bytes after the payload
+    pub(crate) fn trailer_len(&self) -> usize {
+        if self.crc32c { 4 } else { 0 }
+    }
+
+    /// the bytes of a frame with a `header_len` bytes prefix and a `payload_len` bytes payload,
+    /// `ErrorKind::InvalidData` if a corrupt length overflows
+    pub(crate) fn frame_len(&self, header_len: usize, payload_len: u64) -> anyhow::Result<u64> {
+        (header_len as u64)
+            .checked_add(payload_len)
+            .and_then(|len| len.checked_add(self.trailer_len() as u64))
+            .ok_or_else(|| invalid_data(format!("frame length {} overflows", payload_len)))
+    }
+
+    /// append the length prefix of a `len` bytes payload
+    pub(crate) fn encode_header(&self, len: u64, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        match self.prefix {
+            LengthPrefix::U32Le | LengthPrefix::U32Be => {
+                let Ok(len) = u32::try_from(len) else {
+                    anyhow::bail!("frame of {} bytes does not fit a u32 length", len);
+                };
+                if self.prefix == LengthPrefix::U32Le {
+                    out.extend_from_slice(&len.to_le_bytes());
+                } else {
+                    out.extend_from_slice(&len.to_be_bytes());
+                }
+            }
+            LengthPrefix::Varint => {
+                let mut len = len;
+                while len >= 0x80 {
+                    out.push(len as u8 | 0x80);
+                    len >>= 7;
+                }
+                out.push(len as u8);
+            }
+        }
+        Ok(())
+    }
+
+    /// the size of the length prefix at the start of `data` and the payload length,
+    /// `None` if `data` ends within the prefix
+    pub(crate) fn decode_header(&self, data: &[u8]) -> anyhow::Result<Option<(usize, u64)>> {
+        match self.prefix {
+            LengthPrefix::U32Le | LengthPrefix::U32Be => {
+                let Some(bytes) = data.get(..4) else {
+                    return Ok(None);
+                };
+                let bytes = bytes.try_into().unwrap();
+                let len = if self.prefix == LengthPrefix::U32Le {
+                    u32::from_le_bytes(bytes)
+                } else {
+                    u32::from_be_bytes(bytes)
+                };
+                Ok(Some((4, len as u64)))
+            }
+            LengthPrefix::Varint => {
+                let mut len = 0u64;
+                for (i, &b) in data.iter().take(MAX_VARINT_LEN).enumerate() {
+                    len |= ((b & 0x7f) as u64) << (7 * i);
+                    if b & 0x80 == 0 {
+                        return Ok(Some((i + 1, len)));
+                    }
+                }
+                if data.len() >= MAX_VARINT_LEN {
+                    anyhow::bail!("varint frame length longer than {} bytes", MAX_VARINT_LEN);
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// CRC32C (Castagnoli), reflected polynomial
+const CRC32C_POLY: u32 = 0x82f6_3b78;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub(crate) fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub(crate) fn invalid_data(msg: String) -> anyhow::Error {
+    anyhow::Error::new(std::io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+/// writes frames to a `SequentialWriter`
+pub struct FrameWriter {
+    writer: SequentialWriter,
+    format: FrameFormat,
+    max_frame_size: Option<usize>,
+    header: Vec<u8>,
+}
+
+impl FrameWriter {
+    pub fn new(writer: SequentialWriter, format: FrameFormat) -> Self {
+        Self {
+            writer,
+            format,
+            max_frame_size: None,
+            header: Vec::with_capacity(format.max_header_len()),
+        }
+    }
+
+    /// fail on payloads longer than `max_frame_size`
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = Some(max_frame_size);
+        self
+    }
+
+    pub fn write_frame(&mut self, payload: &[u8]) -> anyhow::Result<()> {
+        if let Some(max) = self.max_frame_size
+            && payload.len() > max
+        {
+            anyhow::bail!("frame of {} bytes is larger than {}", payload.len(), max);
+        }
+        self.header.clear();
+        self.format
+            .encode_header(payload.len() as u64, &mut self.header)?;
+        self.writer.write(&self.header)?;
+        self.writer.write(payload)?;
+        if self.format.crc32c {
+            self.writer.write(&crc32c(payload).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// see `SequentialWriter::finish`
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        self.writer.finish()
+    }
+
+    pub fn into_inner(self) -> SequentialWriter {
+        self.writer
+    }
+}
+
+/// reads frames from a `SequentialReader`
+pub struct FrameReader {
+    reader: SequentialReader,
+    format: FrameFormat,
+    max_frame_size: Option<usize>,
+    consumed: usize, // bytes of the last frame handed out of the reader's buffer, consumed on the next call
+    frame: Vec<u8>,  // a frame across buffers
+    failed: bool,    // a call failed, where the next frame starts is unknown
+}
+
+impl FrameReader {
+    pub fn new(reader: SequentialReader, format: FrameFormat) -> Self {
+        Self {
+            reader,
+            format,
+            max_frame_size: None,
+            consumed: 0,
+            frame: vec![],
+            failed: false,
+        }
+    }
+
+    /// fail with `ErrorKind::InvalidData` on a length above `max_frame_size`
+    /// instead of reading that much
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = Some(max_frame_size);
+        self
+    }
+
+    /// the payload of the next frame, valid until the next call. `None` at the end.
+    /// the slice points into the reader's buffer when the frame lies in one, it is copied when it
+    /// crosses into the next buffer. a bad CRC fails with `ErrorKind::InvalidData`,
+    /// a frame cut off by the end with `ErrorKind::UnexpectedEof`.
+    /// after an error the reader is fused, every later call fails as well.
+    pub fn next_frame(&mut self) -> anyhow::Result<Option<&[u8]>> {
+        if self.failed {
+            anyhow::bail!("an earlier frame failed, the start of the next one is unknown");
+        }
+        // cleared on every way out that succeeds
+        self.failed = true;
+        self.consume_last()?;
+        let format = self.format;
+        let trailer_len = format.trailer_len();
+
+        let data = self.reader.fill_buf()?;
+        if data.is_empty() {
+            self.failed = false;
+            return Ok(None);
+        }
+        let mut in_buf = None;
+        if let Some((header_len, payload_len)) = format.decode_header(data)? {
+            let frame_len = format.frame_len(header_len, payload_len)?;
+            if frame_len <= data.len() as u64 {
+                in_buf = Some((header_len, payload_len as usize));
+            }
+        }
+        if let Some((header_len, payload_len)) = in_buf {
+            self.check_len(payload_len as u64)?;
+            // the same buffer again, nothing is consumed in between
+            let data = self.reader.fill_buf()?;
+            let payload = &data[header_len..header_len + payload_len];
+            if format.crc32c {
+                let at = header_len + payload_len;
+                check_crc(payload, &data[at..at + 4])?;
+            }
+            // consuming now could hand the buffer back to the ring while the slice is in use
+            self.consumed = header_len + payload_len + trailer_len;
+            self.failed = false;
+            return Ok(Some(payload));
+        }
+
+        // the frame crosses buffers, the header is read a byte at a time
+        let mut header = Vec::with_capacity(format.max_header_len());
+        let payload_len = loop {
+            if let Some((_, payload_len)) = format.decode_header(&header)? {
+                break payload_len;
+            }
+            let data = self.reader.fill_buf()?;
+            let Some(&b) = data.first() else {
+                return Err(truncated());
+            };
+            header.push(b);
+            self.reader.consume(1)?;
+        };
+        self.check_len(payload_len)?;
+        let len = format.frame_len(0, payload_len)?;
+        // grows with the data actually there, a corrupt length runs into the end of the file
+        // instead of allocating what it claims
+        self.frame.clear();
+        while (self.frame.len() as u64) < len {
+            let data = self.reader.fill_buf()?;
+            if data.is_empty() {
+                return Err(truncated());
+            }
+            let n = (len - self.frame.len() as u64).min(data.len() as u64) as usize;
+            self.frame.extend_from_slice(&data[..n]);
+            self.reader.consume(n)?;
+        }
+        let (payload, crc) = self.frame.split_at(payload_len as usize);
+        if format.crc32c {
+            check_crc(payload, crc)?;
+        }
+        self.failed = false;
+        Ok(Some(payload))
+    }
+
+    pub fn into_inner(mut self) -> anyhow::Result<SequentialReader> {
+        self.consume_last()?;
+        Ok(self.reader)
+    }
+
+    fn consume_last(&mut self) -> anyhow::Result<()> {
+        match std::mem::take(&mut self.consumed) {
+            0 => Ok(()),
+            n => self.reader.consume(n),
+        }
+    }
+
+    fn check_len(&self, payload_len: u64) -> anyhow::Result<()> {
+        if let Some(max) = self.max_frame_size
+            && payload_len > max as u64
+        {
+            return Err(invalid_data(format!(
+                "frame of {} bytes is larger than {}",
+                payload_len, max
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn check_crc(payload: &[u8], stored: &[u8]) -> anyhow::Result<()> {
+    let stored = u32::from_le_bytes(stored.try_into().unwrap());
+    let crc = crc32c(payload);
+    if crc != stored {
+        return Err(invalid_data(format!(
+            "frame crc32c {:08x} does not match {:08x}",
+            crc, stored
+        )));
+    }
+    Ok(())
+}
+
+fn truncated() -> anyhow::Error {
+    anyhow::Error::new(std::io::Error::new(
+        ErrorKind::UnexpectedEof,
+        "frame cut off by the end",
+    ))
+}
@@ -0,0 +1,756 @@
+#![cfg(target_os = "linux")]
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::OpenOptions,
+    os::fd::RawFd,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use io_uring::{opcode, squeue, types};
+
+#[cfg(any(test, feature = "fault-injection"))]
+use super::fault::FaultPlan;
+use super::{
+    buf_ring::ProvidedBufRing,
+    buffer::Buffer,
+    buffer_pool::{BufferPool, PoolInner},
+    direct_file::DirectFile,
+    random_reader::RandomReader,
+    random_writer::RandomWriter,
+    reverse_reader::ReverseReader,
+    ring::{self, Completion, Ring},
+    sequential_reader::{ReaderConfig, SequentialReader},
+    sequential_writer::{SequentialWriter, WriterConfig},
+};
+
+/// the high 32 bits of `user_data` identify the stream, the low 32 bits are the stream's own tag
+const STREAM_SHIFT: u32 = 32;
+const TAG_MASK: u64 = (1 << STREAM_SHIFT) - 1;
+/// tag of the stream's own linked timeouts and cancellations, their completions are not handed out
+const INTERNAL_TAG: u64 = 1 << (STREAM_SHIFT - 1);
+
+/// the state of a ring and the condvar the streams waiting for a kernel waiter sleep on
+pub(crate) struct Shared {
+    inner: Mutex<ContextInner>,
+    routed: Condvar, // notified once the kernel waiter has routed what it reaped
+}
+
+pub(crate) struct ContextInner {
+    ring: Ring,
+    reaping: bool, // a stream waits in the kernel without the lock, it routes for all of them
+    routed: u64,   // completions routed so far
+    queues: HashMap<u32, VecDeque<Completion>>,
+    next_stream: u32,
+    file_slots: Vec<bool>, // true if the registered file slot is in use
+    buf_slots: Vec<bool>,  // true if the registered buffer slot is in use
+    pools: Vec<AttachedPool>,
+    next_bgid: u16,
+}
+
+/// a `BufferPool` whose buffers are registered in the context, each at `base` + its pool index
+struct AttachedPool {
+    pool: Arc<PoolInner>, // keeps the registered memory alive
+    base: usize,
+    registered: Vec<bool>,
+}
+
+impl ContextInner {
+    fn route(&mut self, cqe: Completion) {
+        self.routed += 1;
+        let stream = (cqe.user_data >> STREAM_SHIFT) as u32;
+        // completions of a stream that is already gone are dropped
+        if let Some(queue) = self.queues.get_mut(&stream) {
+            queue.push_back(Completion {
+                user_data: cqe.user_data & TAG_MASK,
+                ..cqe
+            });
+        }
+    }
+
+    fn new_stream(&mut self) -> u32 {
+        let stream = self.next_stream;
+        self.next_stream = self.next_stream.wrapping_add(1);
+        self.queues.insert(stream, VecDeque::new());
+        stream
+    }
+}
+
+/// one io_uring with a registered-file and registered-buffer table shared by many readers and writers.
+///
+/// every stream created from the context tags its requests with its own id in `user_data`,
+/// completions are routed back to a per-stream queue by whoever reaps them,
+/// so a stream waiting for its own buffer also makes progress for all the others.
+/// `IoContext` is a cheap handle, clones refer to the same ring.
+#[derive(Clone)]
+pub struct IoContext {
+    inner: Arc<Shared>,
+}
+
+impl IoContext {
+    /// `entries`: submission queue size, should be at least the total number of buffers of all streams.
+    /// `max_files` / `max_buffers`: size of the registered file and buffer tables.
+    pub fn new(entries: u32, max_files: u32, max_buffers: u32) -> anyhow::Result<Self> {
+        let ring = Ring::new(entries)?;
+        ring.submitter()
+            .register_files_sparse(max_files)
+            .context("register sparse files error")?;
+        ring.submitter()
+            .register_buffers_sparse(max_buffers)
+            .context("register sparse buffers error")?;
+
+        Ok(Self {
+            inner: Arc::new(Shared::new(ContextInner {
+                ring,
+                reaping: false,
+                routed: 0,
+                queues: HashMap::new(),
+                next_stream: 0,
+                file_slots: vec![false; max_files as usize],
+                buf_slots: vec![false; max_buffers as usize],
+                pools: vec![],
+                next_bgid: 0,
+            })),
+        })
+    }
+
+    /// create a `SequentialReader` whose requests go through this context's ring
+    pub fn reader(
+        &self,
+        fpath: &str,
+        start_pos: u64,
+        buffer_size: usize,
+        num_buffer: usize,
+        end_pos: Option<u64>,
+    ) -> anyhow::Result<SequentialReader> {
+        self.reader_with_config(
+            fpath,
+            start_pos,
+            end_pos,
+            ReaderConfig::new(buffer_size, num_buffer),
+        )
+    }
+
+    pub fn reader_with_config(
+        &self,
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        config: ReaderConfig,
+    ) -> anyhow::Result<SequentialReader> {
+        SequentialReader::new_in(Some(self), fpath, start_pos, end_pos, config)
+    }
+
+    /// create a `SequentialWriter` whose requests go through this context's ring
+    pub fn writer(
+        &self,
+        fpath: &str,
+        start_pos: u64,
+        buffer_size: usize,
+        num_buffer: usize,
+    ) -> anyhow::Result<SequentialWriter> {
+        self.writer_with_config(fpath, start_pos, WriterConfig::new(buffer_size, num_buffer))
+    }
+
+    pub fn writer_with_config(
+        &self,
+        fpath: &str,
+        start_pos: u64,
+        config: WriterConfig,
+    ) -> anyhow::Result<SequentialWriter> {
+        SequentialWriter::new_in(Some(self), fpath, start_pos, config)
+    }
+
+    /// create a `ReverseReader` whose requests go through this context's ring
+    pub fn reverse_reader(
+        &self,
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        config: ReaderConfig,
+    ) -> anyhow::Result<ReverseReader> {
+        ReverseReader::new_in(Some(self), fpath, start_pos, end_pos, config)
+    }
+
+    /// create a `RandomReader` whose requests go through this context's ring
+    pub fn random_reader(&self, fpath: &str, queue_depth: usize) -> anyhow::Result<RandomReader> {
+        RandomReader::new_in(Some(self), fpath, queue_depth, None)
+    }
+
+    /// `random_reader` with the buffers of `pool` registered, see `RandomReader::with_pool`
+    pub fn random_reader_with_pool(
+        &self,
+        fpath: &str,
+        queue_depth: usize,
+        pool: BufferPool,
+    ) -> anyhow::Result<RandomReader> {
+        RandomReader::new_in(Some(self), fpath, queue_depth, Some(pool))
+    }
+
+    /// create a `RandomWriter` whose requests go through this context's ring
+    pub fn random_writer(&self, fpath: &str, queue_depth: usize) -> anyhow::Result<RandomWriter> {
+        RandomWriter::new_in(Some(self), fpath, queue_depth)
+    }
+
+    /// create a `DirectFile` whose requests go through this context's ring, O_DIRECT is added to `options`
+    pub fn direct_file(
+        &self,
+        fpath: &str,
+        options: &mut OpenOptions,
+        queue_depth: usize,
+    ) -> anyhow::Result<DirectFile> {
+        DirectFile::new_in(Some(self), fpath, options, queue_depth, None)
+    }
+
+    /// `direct_file` with the buffers of `pool` registered, see `DirectFile::with_pool`
+    pub fn direct_file_with_pool(
+        &self,
+        fpath: &str,
+        options: &mut OpenOptions,
+        queue_depth: usize,
+        pool: BufferPool,
+    ) -> anyhow::Result<DirectFile> {
+        DirectFile::new_in(Some(self), fpath, options, queue_depth, Some(pool))
+    }
+
+    /// submit everything queued by all streams and dispatch the completions that are already available.
+    /// never blocks, returns the number of completions dispatched. while a stream waits in the
+    /// kernel the completions are left to it.
+    pub fn poll(&self) -> anyhow::Result<usize> {
+        let mut inner = self.lock();
+        inner.ring.submit()?;
+        if inner.reaping {
+            return Ok(0);
+        }
+        let mut n = 0;
+        while let Some(cqe) = inner.ring.try_next()? {
+            inner.route(cqe);
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// an eventfd that becomes readable when any stream of the context has a completion,
+    /// for epoll based event loops. `poll` and the `try_*` calls of the streams reset it.
+    pub fn eventfd(&self) -> anyhow::Result<RawFd> {
+        self.lock().ring.eventfd()
+    }
+
+    /// script faults for all requests going through this context from now on
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn set_fault_plan(&self, plan: FaultPlan) {
+        self.lock().ring.set_fault_plan(plan);
+    }
+
+    /// the number of completions routed to the streams so far, it changes whenever
+    /// a stream may have something new in its queue
+    pub(crate) fn routed(&self) -> u64 {
+        self.lock().routed
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ContextInner> {
+        lock(&self.inner)
+    }
+
+    /// register `fd` and `buffers` in free slots of the tables.
+    /// without `fd` the stream can only submit requests on plain file descriptors.
+    pub(crate) fn open_stream(
+        &self,
+        fd: Option<RawFd>,
+        buffers: &mut [Buffer],
+    ) -> anyhow::Result<StreamRing> {
+        let mut inner = self.lock();
+
+        let file_slot = match fd {
+            Some(fd) => {
+                let slot = inner
+                    .file_slots
+                    .iter()
+                    .position(|used| !used)
+                    .context("no free slot in the registered file table")?;
+                inner
+                    .ring
+                    .submitter()
+                    .register_files_update(slot as u32, &[fd])
+                    .context("register file error")?;
+                inner.file_slots[slot] = true;
+                Some(slot as u32)
+            }
+            None => None,
+        };
+
+        let mut stream = StreamRing {
+            ctx: self.inner.clone(),
+            stream: inner.new_stream(),
+            file_slot,
+            buf_indices: Vec::with_capacity(buffers.len()),
+            owned_buf_slots: vec![],
+            buf_groups: vec![],
+            pending: vec![],
+            internal: 0,
+            timeout: None,
+            shared: true,
+        };
+        for buf in buffers.iter_mut() {
+            match inner.buffer_slot(buf, &mut stream.owned_buf_slots) {
+                Ok(slot) => stream.buf_indices.push(slot),
+                Err(e) => {
+                    // dropping `stream` gives back what it took so far, it needs the lock
+                    drop(inner);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(stream)
+    }
+}
+
+impl ContextInner {
+    /// the slot of `buf` in the registered buffer table, registering it if needed.
+    /// slots allocated for buffers that do not belong to a pool are appended to `owned`.
+    fn buffer_slot(&mut self, buf: &mut Buffer, owned: &mut Vec<u16>) -> anyhow::Result<u16> {
+        let iovec = buf.iovec();
+        let slot = match buf.data.pool_slot.as_ref() {
+            Some(pool_slot) => {
+                let pos = match self
+                    .pools
+                    .iter()
+                    .position(|attached| Arc::ptr_eq(&attached.pool, &pool_slot.pool))
+                {
+                    Some(pos) => pos,
+                    None => {
+                        // reserve a slot for every buffer the pool may ever allocate
+                        let capacity = pool_slot.pool.capacity();
+                        let base = self.free_buf_slots(capacity).with_context(|| {
+                            format!(
+                                "no {} contiguous free slots in the registered buffer table for the pool",
+                                capacity
+                            )
+                        })?;
+                        self.buf_slots[base..base + capacity].fill(true);
+                        self.pools.push(AttachedPool {
+                            pool: pool_slot.pool.clone(),
+                            base,
+                            registered: vec![false; capacity],
+                        });
+                        self.pools.len() - 1
+                    }
+                };
+                let attached = &mut self.pools[pos];
+                let slot = attached.base + pool_slot.idx;
+                if attached.registered[pool_slot.idx] {
+                    return Ok(slot as u16);
+                }
+                attached.registered[pool_slot.idx] = true;
+                slot
+            }
+            None => {
+                let slot = self
+                    .free_buf_slots(1)
+                    .context("no free slot in the registered buffer table")?;
+                self.buf_slots[slot] = true;
+                owned.push(slot as u16);
+                slot
+            }
+        };
+        unsafe {
+            self.ring
+                .submitter()
+                .register_buffers_update(slot as u32, &[iovec], None)
+                .context("register buffers error")?;
+        }
+        Ok(slot as u16)
+    }
+
+    /// first index of `n` contiguous free slots of the registered buffer table
+    fn free_buf_slots(&self, n: usize) -> Option<usize> {
+        (0..(self.buf_slots.len() + 1).saturating_sub(n))
+            .find(|&start| self.buf_slots[start..start + n].iter().all(|used| !used))
+    }
+}
+
+impl Shared {
+    fn new(inner: ContextInner) -> Self {
+        Self {
+            inner: Mutex::new(inner),
+            routed: Condvar::new(),
+        }
+    }
+}
+
+fn lock(shared: &Shared) -> MutexGuard<'_, ContextInner> {
+    shared.inner.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// block until more completions are routed or `deadline` passes. one stream at a time waits in
+/// the kernel, without the lock so the others keep pushing and polling their queues. the others
+/// sleep until it has routed what it reaped, their own completions included.
+fn wait_routed<'a>(
+    shared: &'a Shared,
+    mut inner: MutexGuard<'a, ContextInner>,
+    deadline: Option<Instant>,
+) -> anyhow::Result<MutexGuard<'a, ContextInner>> {
+    // what was pushed since the kernel waiter went to sleep would not be submitted until it is back
+    inner.ring.submit()?;
+    // `try_next` leaves the completions it does not return in `ready`
+    let mut routed = false;
+    while let Some(cqe) = inner.ring.next_ready() {
+        inner.route(cqe);
+        routed = true;
+    }
+    if routed {
+        shared.routed.notify_all();
+        return Ok(inner);
+    }
+    if inner.reaping {
+        let inner = match deadline {
+            None => shared.routed.wait(inner).unwrap_or_else(|e| e.into_inner()),
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match shared.routed.wait_timeout(inner, timeout) {
+                    Ok((inner, _)) => inner,
+                    Err(e) => e.into_inner().0,
+                }
+            }
+        };
+        return Ok(inner);
+    }
+    if inner.ring.pending() == 0 {
+        anyhow::bail!("wait on io_uring without any request in flight");
+    }
+
+    let (fd, want) = (inner.ring.fd(), inner.ring.min_wait());
+    inner.reaping = true;
+    drop(inner);
+    let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    let res = ring::enter_wait(fd, want, timeout);
+    let mut inner = lock(shared);
+    inner.reaping = false;
+    inner.ring.collect();
+    while let Some(cqe) = inner.ring.next_ready() {
+        inner.route(cqe);
+    }
+    shared.routed.notify_all();
+    res.map(|()| inner)
+}
+
+/// the part of a ring that belongs to one reader or writer.
+/// `user_data` pushed and returned here is the stream's own tag (at most 32 bits).
+pub(crate) struct StreamRing {
+    ctx: Arc<Shared>,
+    stream: u32,
+    file_slot: Option<u32>,
+    buf_indices: Vec<u16>, // registered buffer index of each of the stream's buffers
+    owned_buf_slots: Vec<u16>, // slots to release when the stream is gone, pool slots stay registered
+    buf_groups: Vec<u16>,      // provided buffer rings registered by the stream
+    pending: Vec<(u64, bool)>, // tag of each request in flight, true if a cancellation means it timed out
+    internal: usize,           // linked timeouts and cancellations in flight
+    timeout: Option<Duration>,
+    shared: bool,
+}
+
+impl StreamRing {
+    /// a ring used by a single stream, with `fd` as file 0 and `buffers` as buffers 0..n
+    pub fn private(entries: u32, fd: RawFd, buffers: &mut [Buffer]) -> anyhow::Result<Self> {
+        let ring = Ring::new(entries)?;
+        let iovecs = buffers
+            .iter_mut()
+            .map(|buf| buf.iovec())
+            .collect::<Vec<_>>();
+        if !iovecs.is_empty() {
+            unsafe {
+                ring.submitter()
+                    .register_buffers(&iovecs)
+                    .context("register buffers error")?;
+            }
+        }
+        ring.submitter()
+            .register_files(&[fd])
+            .context("register file error")?;
+
+        let mut inner = ContextInner {
+            ring,
+            reaping: false,
+            routed: 0,
+            queues: HashMap::new(),
+            next_stream: 0,
+            file_slots: vec![true],
+            buf_slots: vec![true; iovecs.len()],
+            pools: vec![],
+            next_bgid: 0,
+        };
+        let stream = inner.new_stream();
+        Ok(Self {
+            ctx: Arc::new(Shared::new(inner)),
+            stream,
+            file_slot: Some(0),
+            buf_indices: (0..iovecs.len() as u16).collect(),
+            owned_buf_slots: vec![],
+            buf_groups: vec![],
+            pending: vec![],
+            internal: 0,
+            timeout: None,
+            shared: false,
+        })
+    }
+
+    pub fn file(&self) -> types::Fixed {
+        types::Fixed(self.file_slot.expect("stream has no registered file"))
+    }
+
+    /// index in the registered buffer table of the stream's `idx`-th buffer
+    pub fn buf_index(&self, idx: usize) -> u16 {
+        self.buf_indices[idx]
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// hand the pushed requests to the kernel without waiting
+    pub fn submit(&mut self) -> anyhow::Result<()> {
+        lock(&self.ctx).ring.submit()
+    }
+
+    /// register a provided buffer ring, returns its buffer group id
+    pub fn register_buf_ring(&mut self, buf_ring: &ProvidedBufRing) -> anyhow::Result<u16> {
+        let mut inner = lock(&self.ctx);
+        let bgid = inner.next_bgid;
+        unsafe {
+            inner
+                .ring
+                .submitter()
+                .register_buf_ring_with_flags(buf_ring.addr(), buf_ring.entries(), bgid, 0)
+                .context("register provided buffer ring error")?;
+        }
+        inner.next_bgid = inner.next_bgid.wrapping_add(1);
+        self.buf_groups.push(bgid);
+        Ok(bgid)
+    }
+
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn set_fault_plan(&mut self, plan: FaultPlan) {
+        lock(&self.ctx).ring.set_fault_plan(plan);
+    }
+
+    /// see `Ring::eventfd`, shared by all the streams of the ring
+    pub fn eventfd(&mut self) -> anyhow::Result<RawFd> {
+        lock(&self.ctx).ring.eventfd()
+    }
+
+    /// link every request pushed from now on to a timeout, a request still running after
+    /// `timeout` is cancelled and completes with `ETIMEDOUT`
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// # Safety
+    /// the buffers referenced by `sqe` must stay valid until its completion is returned by `wait`
+    pub unsafe fn push(&mut self, sqe: squeue::Entry) -> anyhow::Result<()> {
+        let tag = sqe.get_user_data();
+        debug_assert!(tag < INTERNAL_TAG);
+        let sqe = sqe.user_data(self.user_data(tag));
+        let mut inner = lock(&self.ctx);
+        match self.timeout {
+            Some(timeout) => {
+                unsafe {
+                    inner
+                        .ring
+                        .push_link(&[sqe], Some((timeout, self.user_data(INTERNAL_TAG))))?
+                };
+                self.internal += 1;
+            }
+            None => unsafe { inner.ring.push(&sqe)? },
+        }
+        self.pending.push((tag, self.timeout.is_some()));
+        Ok(())
+    }
+
+    /// push `sqes` as one chain, each starts once the one before it succeeded.
+    /// the rest of the chain completes with `ECANCELED` after a failed or short request.
+    ///
+    /// # Safety
+    /// same as `push`
+    pub unsafe fn push_link(&mut self, sqes: &[squeue::Entry]) -> anyhow::Result<()> {
+        let tags = sqes.iter().map(|sqe| sqe.get_user_data()).collect::<Vec<_>>();
+        debug_assert!(tags.iter().all(|&tag| tag < INTERNAL_TAG));
+        let sqes = sqes
+            .iter()
+            .zip(&tags)
+            .map(|(sqe, &tag)| sqe.clone().user_data(self.user_data(tag)))
+            .collect::<Vec<_>>();
+        let timeout = self
+            .timeout
+            .map(|timeout| (timeout, self.user_data(INTERNAL_TAG)));
+        let mut inner = lock(&self.ctx);
+        unsafe { inner.ring.push_link(&sqes, timeout)? };
+        if timeout.is_some() {
+            self.internal += sqes.len();
+        }
+        for tag in tags {
+            self.pending.push((tag, timeout.is_some()));
+        }
+        Ok(())
+    }
+
+    /// cancel all the requests on the stream's file, they complete with `ECANCELED`
+    pub fn cancel(&mut self) -> anyhow::Result<()> {
+        let sqe = opcode::AsyncCancel2::new(types::CancelBuilder::fd(self.file()).all())
+            .build()
+            .user_data(self.user_data(INTERNAL_TAG));
+        let mut inner = lock(&self.ctx);
+        unsafe { inner.ring.push(&sqe)? };
+        inner.ring.submit()?;
+        self.internal += 1;
+        Ok(())
+    }
+
+    fn user_data(&self, tag: u64) -> u64 {
+        ((self.stream as u64) << STREAM_SHIFT) | tag
+    }
+
+    /// the next completion in the stream's queue, internal ones are swallowed
+    fn pop(&mut self, inner: &mut ContextInner) -> Option<Completion> {
+        let queue = inner.queues.get_mut(&self.stream)?;
+        while let Some(mut cqe) = queue.pop_front() {
+            if cqe.user_data & INTERNAL_TAG != 0 {
+                self.internal -= 1;
+                continue;
+            }
+            let timed = match self
+                .pending
+                .iter()
+                .position(|&(tag, _)| tag == cqe.user_data)
+            {
+                Some(pos) => self.pending.swap_remove(pos).1,
+                None => false,
+            };
+            // only the cancellations by a linked timeout or `wait_deadline` are timeouts
+            if timed && cqe.result == -libc::ECANCELED {
+                cqe.result = -libc::ETIMEDOUT;
+            }
+            return Some(cqe);
+        }
+        None
+    }
+
+    /// return one of this stream's completions if there is any, without blocking
+    pub fn try_wait(&mut self) -> anyhow::Result<Option<Completion>> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let ctx = self.ctx.clone();
+        let mut inner = lock(&ctx);
+        loop {
+            if let Some(cqe) = self.pop(&mut inner) {
+                return Ok(Some(cqe));
+            }
+            // the stream waiting in the kernel routes the completions
+            if inner.reaping {
+                inner.ring.submit()?;
+                return Ok(None);
+            }
+            match inner.ring.try_next()? {
+                Some(cqe) => inner.route(cqe),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// block until one of this stream's requests completes
+    pub fn wait(&mut self) -> anyhow::Result<Completion> {
+        if self.pending.is_empty() {
+            anyhow::bail!("wait on io_uring without any request in flight");
+        }
+        let ctx = self.ctx.clone();
+        let mut inner = lock(&ctx);
+        loop {
+            if let Some(cqe) = self.pop(&mut inner) {
+                return Ok(cqe);
+            }
+            inner = wait_routed(&ctx, inner, None)?;
+        }
+    }
+
+    /// like `wait`, `None` if `deadline` passes first
+    pub fn wait_until(&mut self, deadline: Instant) -> anyhow::Result<Option<Completion>> {
+        if self.pending.is_empty() {
+            anyhow::bail!("wait on io_uring without any request in flight");
+        }
+        let ctx = self.ctx.clone();
+        let mut inner = lock(&ctx);
+        loop {
+            if let Some(cqe) = self.pop(&mut inner) {
+                return Ok(Some(cqe));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            inner = wait_routed(&ctx, inner, Some(deadline))?;
+        }
+    }
+
+    /// like `wait`, but once `deadline` passes all the stream's requests are cancelled,
+    /// the one waited for then completes with `ETIMEDOUT` unless it has finished just in time
+    pub fn wait_deadline(&mut self, deadline: Option<Instant>) -> anyhow::Result<Completion> {
+        if let Some(deadline) = deadline {
+            if let Some(cqe) = self.wait_until(deadline)? {
+                return Ok(cqe);
+            }
+            for (_, timed) in self.pending.iter_mut() {
+                *timed = true;
+            }
+            self.cancel()?;
+        }
+        self.wait()
+    }
+
+    /// wait for everything in flight, including the internal requests
+    fn drain(&mut self) -> anyhow::Result<()> {
+        let ctx = self.ctx.clone();
+        let mut inner = lock(&ctx);
+        loop {
+            while self.pop(&mut inner).is_some() {}
+            if self.pending.is_empty() && self.internal == 0 {
+                return Ok(());
+            }
+            inner = wait_routed(&ctx, inner, None)?;
+        }
+    }
+}
+
+impl Drop for StreamRing {
+    fn drop(&mut self) {
+        // the kernel may still write into the stream's buffers, they are freed right after this.
+        // waiting only fails on a broken ring, there is nothing left to do about it then
+        let _ = self.drain();
+
+        let mut inner = lock(&self.ctx);
+        inner.queues.remove(&self.stream);
+        if !self.shared {
+            return;
+        }
+
+        let submitter = inner.ring.submitter();
+        for &bgid in self.buf_groups.iter() {
+            let _ = submitter.unregister_buf_ring(bgid);
+        }
+        if let Some(slot) = self.file_slot {
+            let _ = submitter.register_files_update(slot, &[-1]);
+        }
+        let empty = libc::iovec {
+            iov_base: std::ptr::null_mut(),
+            iov_len: 0,
+        };
+        for &slot in self.owned_buf_slots.iter() {
+            let _ = unsafe { submitter.register_buffers_update(slot as u32, &[empty], None) };
+        }
+        if let Some(slot) = self.file_slot {
+            inner.file_slots[slot as usize] = false;
+        }
+        for &slot in self.owned_buf_slots.iter() {
+            inner.buf_slots[slot as usize] = false;
+        }
+    }
+}
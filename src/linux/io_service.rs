@@ -0,0 +1,858 @@
+#![cfg(target_os = "linux")]
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender, TryRecvError},
+    },
+    thread::JoinHandle,
+};
+
+use anyhow::Context;
+use io_uring::{opcode, types};
+
+use super::{
+    io_context::{IoContext, StreamRing},
+    sequential_reader::SequentialReader,
+    sequential_writer::SequentialWriter,
+    utils::would_block,
+};
+
+/// the result of a request sent to the `IoService`, delivered once by the service thread
+pub struct Pending<T> {
+    rx: Receiver<anyhow::Result<T>>,
+}
+
+impl<T> Pending<T> {
+    fn new() -> (Sender<anyhow::Result<T>>, Self) {
+        let (tx, rx) = mpsc::channel();
+        (tx, Self { rx })
+    }
+
+    /// block until the service thread has handled the request
+    pub fn wait(self) -> anyhow::Result<T> {
+        self.rx
+            .recv()
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("io service stopped")))
+    }
+
+    /// the result if the request has been handled already
+    pub fn try_wait(&self) -> Option<anyhow::Result<T>> {
+        match self.rx.try_recv() {
+            Ok(res) => Some(res),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(anyhow::anyhow!("io service stopped"))),
+        }
+    }
+}
+
+type Reply<T> = Sender<anyhow::Result<T>>;
+
+enum Request {
+    OpenFile {
+        fpath: String,
+        write: bool,
+        reply: Reply<u64>,
+    },
+    ReadAt {
+        id: u64,
+        offset: u64,
+        len: usize,
+        reply: Reply<Vec<u8>>,
+    },
+    WriteAt {
+        id: u64,
+        offset: u64,
+        data: Vec<u8>,
+        reply: Reply<usize>,
+    },
+    Fsync {
+        id: u64,
+        datasync: bool,
+        reply: Reply<()>,
+    },
+    OpenReader {
+        fpath: String,
+        start_pos: u64,
+        buffer_size: usize,
+        num_buffer: usize,
+        end_pos: Option<u64>,
+        reply: Reply<u64>,
+    },
+    Read {
+        id: u64,
+        len: usize,
+        reply: Reply<Vec<u8>>,
+    },
+    OpenWriter {
+        fpath: String,
+        start_pos: u64,
+        buffer_size: usize,
+        num_buffer: usize,
+        reply: Reply<u64>,
+    },
+    Write {
+        id: u64,
+        data: Vec<u8>,
+        reply: Reply<()>,
+    },
+    Finish {
+        id: u64,
+        reply: Reply<()>,
+    },
+    Close {
+        id: u64,
+    },
+}
+
+/// the error of a request the failed service thread can not handle
+fn failed<T>(e: &anyhow::Error) -> anyhow::Result<T> {
+    Err(anyhow::anyhow!("io service failed: {:#}", e))
+}
+
+impl Request {
+    /// answer the request with the error the service thread failed with
+    fn fail(self, e: &anyhow::Error) {
+        match self {
+            Request::OpenFile { reply, .. }
+            | Request::OpenReader { reply, .. }
+            | Request::OpenWriter { reply, .. } => {
+                let _ = reply.send(failed(e));
+            }
+            Request::ReadAt { reply, .. } | Request::Read { reply, .. } => {
+                let _ = reply.send(failed(e));
+            }
+            Request::WriteAt { reply, .. } => {
+                let _ = reply.send(failed(e));
+            }
+            Request::Fsync { reply, .. }
+            | Request::Write { reply, .. }
+            | Request::Finish { reply, .. } => {
+                let _ = reply.send(failed(e));
+            }
+            Request::Close { .. } => {}
+        }
+    }
+}
+
+/// a thread that owns an `IoContext` and serves I/O requests sent by any number of threads.
+///
+/// `IoService` is a cheap handle, clones talk to the same thread.
+/// the thread exits once every handle (including opened files and streams) is dropped.
+#[derive(Clone)]
+pub struct IoService {
+    tx: Sender<Request>,
+    wake: Arc<OwnedFd>, // an eventfd written after every request, the thread may wait on its ring
+}
+
+impl IoService {
+    /// same arguments as `IoContext::new`
+    pub fn new(entries: u32, max_files: u32, max_buffers: u32) -> anyhow::Result<Self> {
+        let ctx = IoContext::new(entries, max_files, max_buffers)?;
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("create eventfd failed");
+        }
+        let wake = Arc::new(unsafe { OwnedFd::from_raw_fd(fd) });
+        let (tx, rx) = mpsc::channel();
+        let thread_wake = wake.clone();
+        let _: JoinHandle<()> = std::thread::Builder::new()
+            .name("fiox-io-service".to_string())
+            .spawn(move || {
+                let e = match ServiceThread::new(ctx) {
+                    Ok(mut thread) => match thread.run(&rx, thread_wake.as_raw_fd()) {
+                        Ok(()) => return,
+                        Err(e) => {
+                            thread.fail_pending(&e);
+                            e
+                        }
+                    },
+                    Err(e) => e,
+                };
+                // the handles still alive get the error for everything they send from now on
+                for req in rx {
+                    req.fail(&e);
+                }
+            })
+            .context("spawn io service thread failed")?;
+        Ok(Self { tx, wake })
+    }
+
+    /// open a file for positional access, without O_DIRECT so offsets and lengths need no alignment
+    pub fn open_file(&self, fpath: &str, write: bool) -> anyhow::Result<ServiceFile> {
+        let id = self
+            .call(|reply| Request::OpenFile {
+                fpath: fpath.to_string(),
+                write,
+                reply,
+            })
+            .wait()?;
+        Ok(ServiceFile {
+            id,
+            service: self.clone(),
+        })
+    }
+
+    /// a `SequentialReader` living in the service thread
+    pub fn reader(
+        &self,
+        fpath: &str,
+        start_pos: u64,
+        buffer_size: usize,
+        num_buffer: usize,
+        end_pos: Option<u64>,
+    ) -> anyhow::Result<ServiceReader> {
+        let id = self
+            .call(|reply| Request::OpenReader {
+                fpath: fpath.to_string(),
+                start_pos,
+                buffer_size,
+                num_buffer,
+                end_pos,
+                reply,
+            })
+            .wait()?;
+        Ok(ServiceReader {
+            id,
+            service: self.clone(),
+        })
+    }
+
+    /// a `SequentialWriter` living in the service thread
+    pub fn writer(
+        &self,
+        fpath: &str,
+        start_pos: u64,
+        buffer_size: usize,
+        num_buffer: usize,
+    ) -> anyhow::Result<ServiceWriter> {
+        let id = self
+            .call(|reply| Request::OpenWriter {
+                fpath: fpath.to_string(),
+                start_pos,
+                buffer_size,
+                num_buffer,
+                reply,
+            })
+            .wait()?;
+        Ok(ServiceWriter {
+            id,
+            service: self.clone(),
+            finished: false,
+        })
+    }
+
+    fn call<T>(&self, req: impl FnOnce(Reply<T>) -> Request) -> Pending<T> {
+        let (reply, pending) = Pending::new();
+        // if the thread is gone `reply` is dropped with the request and `pending` reports it
+        self.send(req(reply));
+        pending
+    }
+
+    fn close(&self, id: u64) {
+        self.send(Request::Close { id });
+    }
+
+    fn send(&self, req: Request) {
+        if self.tx.send(req).is_ok() {
+            let one = 1u64;
+            // only fails if the counter is full, the thread is woken then anyway
+            unsafe { libc::write(self.wake.as_raw_fd(), &one as *const u64 as *const _, 8) };
+        }
+    }
+}
+
+/// a file opened by the `IoService`, closed when dropped
+pub struct ServiceFile {
+    id: u64,
+    service: IoService,
+}
+
+impl ServiceFile {
+    /// `len` is at most `u32::MAX`, a read can end early like `pread`
+    pub fn read_at(&self, offset: u64, len: usize) -> Pending<Vec<u8>> {
+        self.service.call(|reply| Request::ReadAt {
+            id: self.id,
+            offset,
+            len,
+            reply,
+        })
+    }
+
+    /// resolves to the number of bytes written, `data` is at most `u32::MAX` bytes
+    pub fn write_at(&self, offset: u64, data: Vec<u8>) -> Pending<usize> {
+        self.service.call(|reply| Request::WriteAt {
+            id: self.id,
+            offset,
+            data,
+            reply,
+        })
+    }
+
+    pub fn fsync(&self) -> Pending<()> {
+        self.service.call(|reply| Request::Fsync {
+            id: self.id,
+            datasync: false,
+            reply,
+        })
+    }
+
+    pub fn fdatasync(&self) -> Pending<()> {
+        self.service.call(|reply| Request::Fsync {
+            id: self.id,
+            datasync: true,
+            reply,
+        })
+    }
+}
+
+impl Drop for ServiceFile {
+    fn drop(&mut self) {
+        self.service.close(self.id);
+    }
+}
+
+pub struct ServiceReader {
+    id: u64,
+    service: IoService,
+}
+
+impl ServiceReader {
+    /// the next `len` bytes of the stream, fewer at the end
+    pub fn read(&self, len: usize) -> Pending<Vec<u8>> {
+        self.service.call(|reply| Request::Read {
+            id: self.id,
+            len,
+            reply,
+        })
+    }
+
+    pub fn read2buf(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let data = self.read(buf.len()).wait()?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+impl Drop for ServiceReader {
+    fn drop(&mut self) {
+        self.service.close(self.id);
+    }
+}
+
+pub struct ServiceWriter {
+    id: u64,
+    service: IoService,
+    finished: bool,
+}
+
+impl ServiceWriter {
+    /// queue `data` behind the previous writes
+    pub fn write(&self, data: &[u8]) -> Pending<()> {
+        self.service.call(|reply| Request::Write {
+            id: self.id,
+            data: data.to_vec(),
+            reply,
+        })
+    }
+
+    /// wait for all writes and write the unaligned tail, see `SequentialWriter::finish`
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.finished = true;
+        self.service
+            .call(|reply| Request::Finish { id: self.id, reply })
+            .wait()
+    }
+}
+
+impl Drop for ServiceWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self
+                .service
+                .call(|reply| Request::Finish { id: self.id, reply });
+        }
+        self.service.close(self.id);
+    }
+}
+
+enum Positional {
+    Read(Vec<u8>, Reply<Vec<u8>>),
+    Write(Vec<u8>, Reply<usize>),
+    Fsync(Reply<()>),
+}
+
+/// a request on a reader or writer, it goes on whenever the stream's ring has completions.
+/// the requests of a stream are handled in order.
+enum StreamOp {
+    Read {
+        buf: Vec<u8>,
+        filled: usize,
+        reply: Reply<Vec<u8>>,
+    },
+    Write {
+        data: Vec<u8>,
+        taken: usize,
+        reply: Reply<()>,
+    },
+    Finish {
+        reply: Reply<()>,
+    },
+    Close,
+}
+
+struct ServiceThread {
+    ctx: IoContext,
+    ring: StreamRing, // positional requests
+    next_id: u64,
+    next_tag: u64,
+    files: HashMap<u64, fs::File>,
+    readers: HashMap<u64, SequentialReader>,
+    writers: HashMap<u64, SequentialWriter>,
+    pending: HashMap<u64, Positional>,
+    streams: HashMap<u64, VecDeque<StreamOp>>, // requests waiting for their reader or writer
+}
+
+impl ServiceThread {
+    fn new(ctx: IoContext) -> anyhow::Result<Self> {
+        let ring = ctx.open_stream(None, &mut [])?;
+        Ok(Self {
+            ctx,
+            ring,
+            next_id: 0,
+            next_tag: 0,
+            files: HashMap::new(),
+            readers: HashMap::new(),
+            writers: HashMap::new(),
+            pending: HashMap::new(),
+            streams: HashMap::new(),
+        })
+    }
+
+    /// `wake` is written after every request sent
+    fn run(&mut self, rx: &Receiver<Request>, wake: RawFd) -> anyhow::Result<()> {
+        let eventfd = self.ctx.eventfd()?;
+        loop {
+            self.progress()?;
+
+            let req = if self.pending.is_empty() && self.streams.is_empty() {
+                match rx.recv() {
+                    Ok(req) => req,
+                    Err(_) => return Ok(()),
+                }
+            } else {
+                match rx.try_recv() {
+                    Ok(req) => req,
+                    Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {
+                        // a request or a completion
+                        wait_readable(&[eventfd, wake])?;
+                        let mut count = 0u64;
+                        unsafe { libc::read(wake, &mut count as *mut u64 as *mut _, 8) };
+                        continue;
+                    }
+                }
+            };
+            self.handle(req);
+        }
+    }
+
+    /// complete what the ring has finished until no more completions come in,
+    /// so that none is left in a queue when the thread waits for the eventfd
+    fn progress(&mut self) -> anyhow::Result<()> {
+        loop {
+            let routed = self.ctx.routed();
+            while let Some(cqe) = self.ring.try_wait()? {
+                self.complete(cqe.user_data, cqe.into_result());
+            }
+            let ids = self.streams.keys().copied().collect::<Vec<_>>();
+            for id in ids {
+                self.resume(id);
+            }
+            // submits what the streams pushed
+            self.ctx.poll()?;
+            if self.ctx.routed() == routed {
+                return Ok(());
+            }
+        }
+    }
+
+    /// queue `op` behind the requests of the stream `id` and go on with them
+    fn stream_op(&mut self, id: u64, op: StreamOp) {
+        self.streams.entry(id).or_default().push_back(op);
+        self.resume(id);
+    }
+
+    /// handle the requests of the stream `id` until one has to wait for the ring
+    fn resume(&mut self, id: u64) {
+        let Some(ops) = self.streams.get_mut(&id) else {
+            return;
+        };
+        while let Some(op) = ops.front_mut() {
+            let done = match op {
+                StreamOp::Read { buf, filled, reply } => match self.readers.get_mut(&id) {
+                    Some(reader) => try_read(reader, buf, filled, reply),
+                    None => {
+                        let _ = reply.send(Err(anyhow::anyhow!("unknown reader {}", id)));
+                        true
+                    }
+                },
+                StreamOp::Write { data, taken, reply } => match self.writers.get_mut(&id) {
+                    Some(writer) => try_write(writer, data, taken, reply),
+                    None => {
+                        let _ = reply.send(Err(anyhow::anyhow!("unknown writer {}", id)));
+                        true
+                    }
+                },
+                StreamOp::Finish { reply } => match self.writers.get_mut(&id) {
+                    Some(writer) => match writer.try_finish() {
+                        Err(e) if would_block(&e) => false,
+                        res => {
+                            let _ = reply.send(res);
+                            true
+                        }
+                    },
+                    None => {
+                        let _ = reply.send(Err(anyhow::anyhow!("unknown writer {}", id)));
+                        true
+                    }
+                },
+                StreamOp::Close => {
+                    self.readers.remove(&id);
+                    self.writers.remove(&id);
+                    true
+                }
+            };
+            if !done {
+                return;
+            }
+            ops.pop_front();
+        }
+        self.streams.remove(&id);
+    }
+
+    fn handle(&mut self, req: Request) {
+        match req {
+            Request::OpenFile {
+                fpath,
+                write,
+                reply,
+            } => {
+                let res = OpenOptions::new()
+                    .read(true)
+                    .write(write)
+                    .create(write)
+                    .truncate(false)
+                    .open(&fpath)
+                    .with_context(|| format!("open {} failed", fpath))
+                    .map(|file| {
+                        let id = self.new_id();
+                        self.files.insert(id, file);
+                        id
+                    });
+                let _ = reply.send(res);
+            }
+            Request::ReadAt {
+                id,
+                offset,
+                len,
+                reply,
+            } => {
+                let mut buf = vec![];
+                let res = io_len(len).and_then(|n| {
+                    let fd = self.fd(id)?;
+                    buf.resize(len, 0);
+                    let sqe = opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), n)
+                        .offset(offset)
+                        .build();
+                    self.push(sqe)
+                });
+                match res {
+                    Ok(tag) => {
+                        self.pending.insert(tag, Positional::Read(buf, reply));
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+            }
+            Request::WriteAt {
+                id,
+                offset,
+                data,
+                reply,
+            } => {
+                let res = io_len(data.len()).and_then(|n| {
+                    let fd = self.fd(id)?;
+                    let sqe = opcode::Write::new(types::Fd(fd), data.as_ptr(), n)
+                        .offset(offset)
+                        .build();
+                    self.push(sqe)
+                });
+                match res {
+                    Ok(tag) => {
+                        self.pending.insert(tag, Positional::Write(data, reply));
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+            }
+            Request::Fsync {
+                id,
+                datasync,
+                reply,
+            } => {
+                let res = self.fd(id).and_then(|fd| {
+                    let mut op = opcode::Fsync::new(types::Fd(fd));
+                    if datasync {
+                        op = op.flags(types::FsyncFlags::DATASYNC);
+                    }
+                    self.push(op.build())
+                });
+                match res {
+                    Ok(tag) => {
+                        self.pending.insert(tag, Positional::Fsync(reply));
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+            }
+            Request::OpenReader {
+                fpath,
+                start_pos,
+                buffer_size,
+                num_buffer,
+                end_pos,
+                reply,
+            } => {
+                let res = self
+                    .ctx
+                    .reader(&fpath, start_pos, buffer_size, num_buffer, end_pos)
+                    .map(|reader| {
+                        let id = self.new_id();
+                        self.readers.insert(id, reader);
+                        id
+                    });
+                let _ = reply.send(res);
+            }
+            Request::Read { id, len, reply } => self.stream_op(
+                id,
+                StreamOp::Read {
+                    buf: vec![0_u8; len],
+                    filled: 0,
+                    reply,
+                },
+            ),
+            Request::OpenWriter {
+                fpath,
+                start_pos,
+                buffer_size,
+                num_buffer,
+                reply,
+            } => {
+                let res = self
+                    .ctx
+                    .writer(&fpath, start_pos, buffer_size, num_buffer)
+                    .map(|writer| {
+                        let id = self.new_id();
+                        self.writers.insert(id, writer);
+                        id
+                    });
+                let _ = reply.send(res);
+            }
+            Request::Write { id, data, reply } => self.stream_op(
+                id,
+                StreamOp::Write {
+                    data,
+                    taken: 0,
+                    reply,
+                },
+            ),
+            Request::Finish { id, reply } => self.stream_op(id, StreamOp::Finish { reply }),
+            Request::Close { id } => {
+                if self.files.remove(&id).is_none() {
+                    self.stream_op(id, StreamOp::Close);
+                }
+            }
+        }
+    }
+
+    fn complete(&mut self, tag: u64, res: std::io::Result<u32>) {
+        let Some(op) = self.pending.remove(&tag) else {
+            return;
+        };
+        match op {
+            Positional::Read(mut buf, reply) => {
+                let res = res.map(|n| {
+                    buf.truncate(n as usize);
+                    buf
+                });
+                let _ = reply.send(res.context("read_at failed"));
+            }
+            Positional::Write(data, reply) => {
+                // the kernel is done with the buffer
+                drop(data);
+                let _ = reply.send(res.map(|n| n as usize).context("write_at failed"));
+            }
+            Positional::Fsync(reply) => {
+                let _ = reply.send(res.map(|_| ()).context("fsync failed"));
+            }
+        }
+    }
+
+    /// answer the positional requests in flight and the waiting stream requests with `e`.
+    /// positional buffers are leaked if the ring can not be waited for any more,
+    /// the kernel may still use them.
+    fn fail_pending(&mut self, e: &anyhow::Error) {
+        while self.ring.in_flight() > 0 {
+            match self.ring.wait() {
+                Ok(cqe) => self.complete(cqe.user_data, cqe.into_result()),
+                Err(_) => break,
+            }
+        }
+        let leak = self.ring.in_flight() > 0;
+        for (_, op) in self.pending.drain() {
+            match op {
+                Positional::Read(buf, reply) => {
+                    let _ = reply.send(failed(e));
+                    if leak {
+                        std::mem::forget(buf);
+                    }
+                }
+                Positional::Write(data, reply) => {
+                    let _ = reply.send(failed(e));
+                    if leak {
+                        std::mem::forget(data);
+                    }
+                }
+                Positional::Fsync(reply) => {
+                    let _ = reply.send(failed(e));
+                }
+            }
+        }
+        for (_, ops) in self.streams.drain() {
+            for op in ops {
+                match op {
+                    StreamOp::Read { reply, .. } => {
+                        let _ = reply.send(failed(e));
+                    }
+                    StreamOp::Write { reply, .. } | StreamOp::Finish { reply } => {
+                        let _ = reply.send(failed(e));
+                    }
+                    StreamOp::Close => {}
+                }
+            }
+        }
+    }
+
+    fn fd(&self, id: u64) -> anyhow::Result<i32> {
+        self.files
+            .get(&id)
+            .map(|f| f.as_raw_fd())
+            .with_context(|| format!("unknown file {}", id))
+    }
+
+    fn push(&mut self, sqe: io_uring::squeue::Entry) -> anyhow::Result<u64> {
+        let tag = self.next_tag;
+        self.next_tag = (self.next_tag + 1) & u32::MAX as u64;
+        // the buffer is kept in `pending` until the completion arrives
+        unsafe { self.ring.push(sqe.user_data(tag))? };
+        Ok(tag)
+    }
+
+    fn new_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+/// fill `buf` from `filled` on, `false` if the reader has to wait for the ring
+fn try_read(
+    reader: &mut SequentialReader,
+    buf: &mut Vec<u8>,
+    filled: &mut usize,
+    reply: &Reply<Vec<u8>>,
+) -> bool {
+    while *filled < buf.len() {
+        match reader.try_read(&mut buf[*filled..]) {
+            Ok(0) => break,
+            Ok(n) => *filled += n,
+            Err(e) if would_block(&e) => return false,
+            Err(e) => {
+                let _ = reply.send(Err(e));
+                return true;
+            }
+        }
+    }
+    buf.truncate(*filled);
+    let _ = reply.send(Ok(std::mem::take(buf)));
+    true
+}
+
+/// hand `data` from `taken` on to the writer, `false` if it has to wait for the ring
+fn try_write(
+    writer: &mut SequentialWriter,
+    data: &[u8],
+    taken: &mut usize,
+    reply: &Reply<()>,
+) -> bool {
+    while *taken < data.len() {
+        match writer.try_write(&data[*taken..]) {
+            Ok(n) => *taken += n,
+            Err(e) if would_block(&e) => return false,
+            Err(e) => {
+                let _ = reply.send(Err(e));
+                return true;
+            }
+        }
+    }
+    let _ = reply.send(Ok(()));
+    true
+}
+
+/// the length of a positional request, a single io_uring request takes at most `u32::MAX` bytes
+fn io_len(len: usize) -> anyhow::Result<u32> {
+    u32::try_from(len).map_err(|_| {
+        anyhow::anyhow!(
+            "request of {} bytes is larger than {} bytes, split it",
+            len,
+            u32::MAX
+        )
+    })
+}
+
+/// block until one of `fds` is readable
+fn wait_readable(fds: &[RawFd]) -> anyhow::Result<()> {
+    let mut pollfds = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect::<Vec<_>>();
+    loop {
+        if unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, -1) } >= 0 {
+            return Ok(());
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e).context("poll eventfd failed");
+        }
+    }
+}
+
+impl Drop for ServiceThread {
+    fn drop(&mut self) {
+        // positional buffers must outlive their requests
+        while self.ring.in_flight() > 0 {
+            match self.ring.wait() {
+                Ok(cqe) => self.complete(cqe.user_data, cqe.into_result()),
+                Err(_) => break,
+            }
+        }
+    }
+}
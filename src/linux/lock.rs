@@ -0,0 +1,72 @@
+#![cfg(target_os = "linux")]
+//! advisory `flock` locks taken by the streams on their files
+
+use std::{fmt, fs, io, os::fd::AsRawFd};
+
+/// whether a stream locks its file, writers take an exclusive lock and readers a shared one.
+/// the lock only keeps out other streams that lock too, it is released on `finish` or drop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockMode {
+    #[default]
+    None,
+    /// fail with `Locked` if another stream holds a conflicting lock
+    Try,
+    /// wait until the conflicting locks are released
+    Blocking,
+}
+
+/// the file is locked by another stream, see `LockMode::Try`
+#[derive(Debug)]
+pub struct Locked {
+    pub fpath: String,
+}
+
+impl fmt::Display for Locked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is locked by another stream", self.fpath)
+    }
+}
+
+impl std::error::Error for Locked {}
+
+pub(crate) fn lock(
+    file: &fs::File,
+    fpath: &str,
+    exclusive: bool,
+    mode: LockMode,
+) -> anyhow::Result<()> {
+    let mut op = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+    match mode {
+        LockMode::None => return Ok(()),
+        LockMode::Try => op |= libc::LOCK_NB,
+        LockMode::Blocking => {}
+    }
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), op) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EWOULDBLOCK) => {
+                return Err(Locked {
+                    fpath: fpath.to_string(),
+                }
+                .into());
+            }
+            _ => return Err(anyhow::Error::new(err).context(format!("flock {} failed", fpath))),
+        }
+    }
+}
+
+pub(crate) fn unlock(file: &fs::File, fpath: &str) -> anyhow::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+        return Err(anyhow::Error::new(io::Error::last_os_error())
+            .context(format!("unlock {} failed", fpath)));
+    }
+    Ok(())
+}
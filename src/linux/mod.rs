@@ -1,7 +1,26 @@
+#![cfg(target_os = "linux")]
 pub mod utils;
+pub mod async_io;
+pub(crate) mod atomic;
+pub mod buf_ring;
 pub mod buffer;
+pub mod buffer_pool;
+pub mod direct_file;
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod fault;
+pub mod frame;
+pub mod io_context;
+pub mod io_service;
+pub mod lock;
+pub mod multi_file_reader;
+pub mod par_chunks;
+pub mod parallel_reader;
+pub mod parallel_writer;
+pub(crate) mod positional;
+pub mod random_reader;
+pub mod random_writer;
+pub mod reverse_reader;
+pub mod ring;
 pub mod sequential_reader;
 pub mod sequential_writer;
-
-
-
+pub mod split;
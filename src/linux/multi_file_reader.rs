@@ -0,0 +1,212 @@
+#![cfg(target_os = "linux")]
+use anyhow::Context;
+
+use super::{
+    buffer::Buffer,
+    sequential_reader::{ReaderConfig, SequentialReader},
+};
+
+/// a file, or the `[start, end)` part of it, read by a `MultiFileReader`
+#[derive(Clone, Debug)]
+pub struct FileRange {
+    pub fpath: String,
+    pub start: u64,
+    /// the end of the file if `None`
+    pub end: Option<u64>,
+}
+
+impl FileRange {
+    pub fn new(fpath: &str, start: u64, end: Option<u64>) -> Self {
+        Self {
+            fpath: fpath.to_string(),
+            start,
+            end,
+        }
+    }
+}
+
+impl From<&str> for FileRange {
+    fn from(fpath: &str) -> Self {
+        Self::new(fpath, 0, None)
+    }
+}
+
+/// where the data of a `MultiFileReader::read_segment` comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Origin {
+    /// index of the file in the list given to the reader
+    pub file_idx: usize,
+    /// file position of the first byte
+    pub offset: u64,
+    pub len: usize,
+}
+
+/// one opened file of the list
+struct Part {
+    file_idx: usize,
+    reader: SequentialReader,
+    pos: u64, // file position of the next byte to hand out
+    end: u64,
+}
+
+/// reads a list of files, or parts of them, one after the other as one stream.
+/// the reads of the next file are submitted once the rest of the current one fits in the buffers,
+/// so there is no gap at the file boundaries. with `config.pool` the next file is only read ahead
+/// while the pool has a second set of buffers free.
+pub struct MultiFileReader {
+    files: Vec<FileRange>,
+    config: ReaderConfig,
+    current: Option<Part>,
+    next: Option<Part>,
+    next_idx: usize, // the file to open next
+}
+
+impl MultiFileReader {
+    pub fn new(
+        files: Vec<FileRange>,
+        buffer_size: usize,
+        num_buffer: usize,
+    ) -> anyhow::Result<Self> {
+        Self::with_config(files, ReaderConfig::new(buffer_size, num_buffer))
+    }
+
+    /// every file is read with `config`, `follow` is not supported
+    pub fn with_config(files: Vec<FileRange>, config: ReaderConfig) -> anyhow::Result<Self> {
+        if config.follow.is_some() {
+            anyhow::bail!("MultiFileReader does not support follow");
+        }
+        Ok(Self {
+            files,
+            config,
+            current: None,
+            next: None,
+            next_idx: 0,
+        })
+    }
+
+    pub fn files(&self) -> &[FileRange] {
+        &self.files
+    }
+
+    /// the file index and position of the next byte, `None` at the end
+    pub fn position(&mut self) -> anyhow::Result<Option<(usize, u64)>> {
+        self.skip_finished()?;
+        Ok(self.current.as_ref().map(|part| (part.file_idx, part.pos)))
+    }
+
+    /// fill `buf` from the files in order, less than `buf.len()` only at the end of the last file
+    pub fn read2buf(&mut self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.read_segment(&mut buf[filled..])? {
+                Some(origin) => filled += origin.len,
+                None => break,
+            }
+        }
+        Ok(filled)
+    }
+
+    /// read into `buf` from the current file only, `None` at the end of the last file
+    pub fn read_segment(&mut self, buf: &mut [u8]) -> anyhow::Result<Option<Origin>> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        self.skip_finished()?;
+        let Some(part) = self.current.as_mut() else {
+            return Ok(None);
+        };
+        let want = buf.len().min((part.end - part.pos) as usize);
+        let n = part.reader.read2buf(&mut buf[..want])?;
+        if n < want {
+            anyhow::bail!(
+                "{} ended at {} before {}",
+                self.files[part.file_idx].fpath,
+                part.pos + n as u64,
+                part.end
+            );
+        }
+        let origin = Origin {
+            file_idx: part.file_idx,
+            offset: part.pos,
+            len: n,
+        };
+        part.pos += n as u64;
+        self.prefetch()?;
+        Ok(Some(origin))
+    }
+
+    /// move on to the next file with data once the current one is read to its end
+    fn skip_finished(&mut self) -> anyhow::Result<()> {
+        loop {
+            if let Some(part) = self.current.as_ref()
+                && part.pos < part.end
+            {
+                return Ok(());
+            }
+            // the buffers of the finished file go back to the pool before the next one takes some
+            self.current = None;
+            self.current = match self.next.take() {
+                Some(part) => Some(part),
+                None if self.next_idx < self.files.len() => Some(self.open_next(None)?),
+                None => None,
+            };
+            if self.current.is_none() {
+                return Ok(());
+            }
+            self.prefetch()?;
+        }
+    }
+
+    /// start reading the next file once the rest of the current one fits in the buffers
+    fn prefetch(&mut self) -> anyhow::Result<()> {
+        let Some(part) = self.current.as_ref() else {
+            return Ok(());
+        };
+        let read_ahead = (self.config.buffer_size * self.config.num_buffer) as u64;
+        if self.next.is_some()
+            || self.next_idx >= self.files.len()
+            || part.end - part.pos > read_ahead
+        {
+            return Ok(());
+        }
+        // waiting for pool buffers here could wait for the ones the current file holds
+        let buffers = match self.config.pool.as_ref() {
+            Some(pool) => match pool.try_acquire(self.config.num_buffer)? {
+                Some(buffers) => Some(buffers),
+                None => return Ok(()),
+            },
+            None => None,
+        };
+        let mut next = self.open_next(buffers)?;
+        next.reader.start_reads()?;
+        self.next = Some(next);
+        Ok(())
+    }
+
+    /// `buffers` taken from the pool already
+    fn open_next(&mut self, buffers: Option<Vec<Buffer>>) -> anyhow::Result<Part> {
+        let file_idx = self.next_idx;
+        self.next_idx += 1;
+        let range = &self.files[file_idx];
+        let end = match range.end {
+            Some(end) => end,
+            None => std::fs::metadata(&range.fpath)
+                .with_context(|| format!("stat {} failed", range.fpath))?
+                .len(),
+        };
+        let start = range.start.min(end);
+        let config = self.config.clone();
+        let reader = match buffers {
+            Some(buffers) => {
+                SequentialReader::with_buffers(&range.fpath, start, Some(end), config, buffers)?
+            }
+            None => SequentialReader::with_config(&range.fpath, start, Some(end), config)?,
+        };
+        Ok(Part {
+            file_idx,
+            reader,
+            pos: start,
+            end,
+        })
+    }
+}
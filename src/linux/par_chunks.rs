@@ -0,0 +1,330 @@
+#![cfg(target_os = "linux")]
+use std::{
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender},
+    },
+    thread,
+};
+
+use super::{
+    frame::invalid_data,
+    sequential_reader::{ReaderConfig, SequentialReader},
+    split::Records,
+    utils::get_page_size,
+};
+
+/// settings of `par_chunks`
+#[derive(Clone)]
+pub struct ChunkOptions {
+    /// bytes of a chunk, a record-aligned chunk is cut at the last record that fits
+    /// and grows when a single record does not fit
+    pub chunk_size: usize,
+    pub num_threads: usize,
+    /// chunks read but not mapped yet, this bounds the memory used
+    pub in_flight: usize,
+    /// cut the chunks on record boundaries
+    pub records: Option<Records>,
+    pub reader: ReaderConfig,
+}
+
+impl ChunkOptions {
+    /// `2 * num_threads` chunks in flight, read with two buffers of `chunk_size`
+    pub fn new(chunk_size: usize, num_threads: usize) -> Self {
+        Self {
+            chunk_size,
+            num_threads,
+            in_flight: 2 * num_threads,
+            records: None,
+            reader: ReaderConfig::new(chunk_size.max(1).next_multiple_of(get_page_size()), 2),
+        }
+    }
+
+    pub fn records(mut self, records: impl Into<Records>) -> Self {
+        self.records = Some(records.into());
+        self
+    }
+}
+
+/// a part of the file handed to the map function. `data` is a copy of the reader's buffers,
+/// a record-aligned chunk has to hold the start of a record cut off the chunk before it
+/// and the reader's buffers go back to the ring while the chunks are mapped.
+/// the `in_flight` chunk buffers are reused.
+#[derive(Clone, Copy, Debug)]
+pub struct Chunk<'a> {
+    /// chunks are numbered from 0 in file order
+    pub index: usize,
+    /// file position of `data`
+    pub offset: u64,
+    pub data: &'a [u8],
+}
+
+/// read a file in chunks and map them on a pool of threads while the reads go on,
+/// see `ParChunks::map`
+pub fn par_chunks(fpath: &str, options: ChunkOptions) -> ParChunks {
+    ParChunks {
+        fpath: fpath.to_string(),
+        options,
+    }
+}
+
+/// see `par_chunks`
+pub struct ParChunks {
+    fpath: String,
+    options: ChunkOptions,
+}
+
+impl ParChunks {
+    /// call `f` on every chunk, on any of the threads
+    pub fn map<F, R>(self, f: F) -> ParMap<F>
+    where
+        F: Fn(Chunk<'_>) -> R + Sync,
+        R: Send,
+    {
+        ParMap { chunks: self, f }
+    }
+
+    /// the results of `f` in chunk order
+    pub fn map_collect<F, R>(self, f: F) -> anyhow::Result<Vec<R>>
+    where
+        F: Fn(Chunk<'_>) -> R + Sync,
+        R: Send,
+    {
+        self.map(f).collect()
+    }
+}
+
+/// see `ParChunks::map`
+pub struct ParMap<F> {
+    chunks: ParChunks,
+    f: F,
+}
+
+/// what the reading and mapping threads report
+enum Msg<R> {
+    Mapped(usize, R),
+    Total(usize),
+    Failed(anyhow::Error),
+}
+
+impl<F> ParMap<F> {
+    /// fold the results into `init` in chunk order, on the calling thread
+    pub fn reduce<R, T, G>(self, init: T, mut op: G) -> anyhow::Result<T>
+    where
+        F: Fn(Chunk<'_>) -> R + Sync,
+        R: Send,
+        G: FnMut(T, R) -> T,
+    {
+        let mut acc = Some(init);
+        self.run(|r| acc = Some(op(acc.take().unwrap(), r)))?;
+        Ok(acc.unwrap())
+    }
+
+    /// the results in chunk order
+    pub fn collect<R>(self) -> anyhow::Result<Vec<R>>
+    where
+        F: Fn(Chunk<'_>) -> R + Sync,
+        R: Send,
+    {
+        self.reduce(vec![], |mut out, r| {
+            out.push(r);
+            out
+        })
+    }
+
+    fn run<R>(self, mut sink: impl FnMut(R)) -> anyhow::Result<()>
+    where
+        F: Fn(Chunk<'_>) -> R + Sync,
+        R: Send,
+    {
+        let ParMap {
+            chunks: ParChunks { fpath, options },
+            f,
+        } = self;
+        if options.chunk_size == 0 || options.num_threads == 0 || options.in_flight == 0 {
+            anyhow::bail!("chunk_size, num_threads and in_flight must not be 0");
+        }
+
+        let abort = AtomicBool::new(false);
+        let (work_tx, work_rx) = mpsc::sync_channel(options.in_flight);
+        let work_rx = Mutex::new(work_rx);
+        let (free_tx, free_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        thread::scope(|s| {
+            let (abort, work_rx, f) = (&abort, &work_rx, &f);
+            let done = done_tx.clone();
+            let (fpath, options) = (&fpath, &options);
+            s.spawn(move || read_chunks(fpath, options, abort, work_tx, free_rx, done));
+            for _ in 0..options.num_threads {
+                let done = done_tx.clone();
+                let free = free_tx.clone();
+                s.spawn(move || map_chunks(f, work_rx, free, done));
+            }
+            drop(done_tx);
+
+            // the results come in any order, they are passed on in chunk order
+            let mut mapped = BTreeMap::new();
+            let mut next = 0;
+            let mut total = None;
+            while total != Some(next) {
+                let msg = match done_rx.recv() {
+                    Ok(msg) => msg,
+                    Err(_) => anyhow::bail!("par_chunks threads of {} exited", fpath),
+                };
+                match msg {
+                    Msg::Mapped(index, r) => {
+                        mapped.insert(index, r);
+                        while let Some(r) = mapped.remove(&next) {
+                            sink(r);
+                            next += 1;
+                        }
+                    }
+                    Msg::Total(n) => total = Some(n),
+                    Msg::Failed(e) => {
+                        abort.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// the reading thread, it cuts the chunks and keeps at most `in_flight` of them
+fn read_chunks<R>(
+    fpath: &str,
+    options: &ChunkOptions,
+    abort: &AtomicBool,
+    work: SyncSender<(usize, u64, Vec<u8>)>,
+    free: Receiver<Vec<u8>>,
+    done: Sender<Msg<R>>,
+) {
+    let msg = match read_chunks_inner(fpath, options, abort, work, free) {
+        Ok(total) => Msg::Total(total),
+        Err(e) => Msg::Failed(e),
+    };
+    let _ = done.send(msg);
+}
+
+/// the number of chunks sent
+fn read_chunks_inner(
+    fpath: &str,
+    options: &ChunkOptions,
+    abort: &AtomicBool,
+    work: SyncSender<(usize, u64, Vec<u8>)>,
+    free: Receiver<Vec<u8>>,
+) -> anyhow::Result<usize> {
+    let mut reader = SequentialReader::with_config(fpath, 0, None, options.reader.clone())?;
+    let mut allocated = 0;
+    let mut carry = vec![]; // the start of a record cut off the last chunk
+    let mut index = 0;
+    let mut offset = 0;
+    let mut eof = false;
+    while !eof {
+        if abort.load(Ordering::Relaxed) {
+            anyhow::bail!("aborted");
+        }
+        let mut buf = if allocated < options.in_flight {
+            allocated += 1;
+            Vec::with_capacity(options.chunk_size)
+        } else {
+            match free.recv() {
+                Ok(buf) => buf,
+                Err(_) => anyhow::bail!("par_chunks threads of {} exited", fpath),
+            }
+        };
+        buf.clear();
+        buf.append(&mut carry);
+
+        let mut want = options.chunk_size;
+        let end = loop {
+            if buf.len() < want {
+                let filled = buf.len();
+                buf.resize(want, 0);
+                let n = reader.read2buf(&mut buf[filled..])?;
+                buf.truncate(filled + n);
+                eof = buf.len() < want;
+            }
+            if eof {
+                break buf.len();
+            }
+            match options.records {
+                None => break buf.len(),
+                Some(records) => match last_record_end(records, &buf)? {
+                    Some(end) => break end,
+                    // a record longer than the chunk
+                    None => want += options.chunk_size,
+                },
+            }
+        };
+        carry.extend_from_slice(&buf[end..]);
+        buf.truncate(end);
+        if buf.is_empty() {
+            break;
+        }
+        let len = buf.len() as u64;
+        if work.send((index, offset, buf)).is_err() {
+            anyhow::bail!("par_chunks threads of {} exited", fpath);
+        }
+        index += 1;
+        offset += len;
+    }
+    Ok(index)
+}
+
+/// the end of the last whole record in `data`, `None` if there is none
+fn last_record_end(records: Records, data: &[u8]) -> anyhow::Result<Option<usize>> {
+    match records {
+        Records::Delimiter(delim) => Ok(data.iter().rposition(|&b| b == delim).map(|i| i + 1)),
+        Records::Frames(format) => {
+            let mut pos = 0;
+            while let Some((header_len, payload_len)) = format.decode_header(&data[pos..])? {
+                let frame_len = format.frame_len(header_len, payload_len)?;
+                match (pos as u64).checked_add(frame_len) {
+                    Some(end) if end <= data.len() as u64 => pos = end as usize,
+                    Some(_) => break,
+                    None => {
+                        return Err(invalid_data(format!(
+                            "frame of {} bytes at {} overflows",
+                            frame_len, pos
+                        )));
+                    }
+                }
+            }
+            Ok((pos > 0).then_some(pos))
+        }
+    }
+}
+
+/// a mapping thread
+fn map_chunks<F, R>(
+    f: &F,
+    work: &Mutex<Receiver<(usize, u64, Vec<u8>)>>,
+    free: Sender<Vec<u8>>,
+    done: Sender<Msg<R>>,
+) where
+    F: Fn(Chunk<'_>) -> R + Sync,
+{
+    loop {
+        let msg = work.lock().unwrap().recv();
+        let Ok((index, offset, data)) = msg else {
+            return;
+        };
+        let chunk = Chunk {
+            index,
+            offset,
+            data: &data,
+        };
+        let msg = match panic::catch_unwind(AssertUnwindSafe(|| f(chunk))) {
+            Ok(r) => Msg::Mapped(index, r),
+            Err(_) => Msg::Failed(anyhow::anyhow!("map of chunk {} panicked", index)),
+        };
+        let _ = done.send(msg);
+        let _ = free.send(data);
+    }
+}
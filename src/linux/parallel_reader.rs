@@ -0,0 +1,414 @@
+#![cfg(target_os = "linux")]
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::Context;
+
+use super::{
+    buffer::Buffer, buffer_pool::stream_buffers, io_context::StreamRing, lock,
+    sequential_reader::ReaderConfig, utils::get_page_size,
+};
+
+/// how a parallel stream splits its range
+#[derive(Clone, Copy, Debug)]
+pub struct StripeConfig {
+    /// bytes of each stripe, a multiple of the page size
+    pub stripe_size: u64,
+    /// stripe `i` goes to thread `i % num_threads`, each thread has its own rings
+    pub num_threads: usize,
+}
+
+impl StripeConfig {
+    pub fn new(stripe_size: u64, num_threads: usize) -> Self {
+        Self {
+            stripe_size,
+            num_threads,
+        }
+    }
+
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        if self.num_threads == 0 {
+            anyhow::bail!("num_threads must not be 0");
+        }
+        if self.stripe_size == 0 || !self.stripe_size.is_multiple_of(get_page_size() as u64) {
+            anyhow::bail!(
+                "stripe_size {} is not a multiple of the page size",
+                self.stripe_size
+            );
+        }
+        Ok(())
+    }
+}
+
+/// a buffer of a reading thread with the data of one read, on its way to the consumer and back
+struct Chunk {
+    buf_idx: usize, // in the thread's registered buffers
+    buf: Buffer,
+    begin: usize,
+    end: usize,
+}
+
+impl Chunk {
+    fn data(&self) -> &[u8] {
+        &self.buf[self.begin..self.end]
+    }
+}
+
+/// a reading thread, it sends the data of its stripes in order
+struct Worker {
+    data: Receiver<anyhow::Result<Chunk>>,
+    free: SyncSender<Chunk>, // drained chunks go back to the thread
+    handle: JoinHandle<()>,
+}
+
+/// reads `[start_pos, end_pos)` of a file with several threads, one ring each, and hands the data
+/// out in file order. the range is split into stripes dealt round robin to the threads, a thread
+/// reads its stripes one after the other into its registered buffers and passes the buffers on.
+pub struct ParallelSequentialReader {
+    workers: Vec<Worker>,
+    start_pos: u64,
+    end_pos: u64,
+    stripe_size: u64,
+    stripe: u64,                   // the stripe handed out now
+    stripe_left: u64,              // bytes of it not received yet
+    chunk: Option<(Chunk, usize)>, // the chunk handed out now and the bytes of it consumed
+}
+
+impl ParallelSequentialReader {
+    /// `end_pos`: the end of the file if `None`
+    pub fn new(
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        stripe: StripeConfig,
+        buffer_size: usize,
+        num_buffer: usize,
+    ) -> anyhow::Result<Self> {
+        Self::with_config(
+            fpath,
+            start_pos,
+            end_pos,
+            stripe,
+            ReaderConfig::new(buffer_size, num_buffer),
+        )
+    }
+
+    /// every thread reads with `config`, `provided_buffers` and `follow` are not supported
+    pub fn with_config(
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        stripe: StripeConfig,
+        config: ReaderConfig,
+    ) -> anyhow::Result<Self> {
+        stripe.check()?;
+        if config.provided_buffers || config.follow.is_some() {
+            anyhow::bail!("ParallelSequentialReader supports neither provided buffers nor follow");
+        }
+        if config.buffer_size == 0 || !config.buffer_size.is_multiple_of(get_page_size()) {
+            anyhow::bail!(
+                "buffer_size {} is not a multiple of the page size",
+                config.buffer_size
+            );
+        }
+        let end_pos = match end_pos {
+            Some(end_pos) => end_pos,
+            None => std::fs::metadata(fpath)
+                .with_context(|| format!("stat {} failed", fpath))?
+                .len(),
+        };
+        let start_pos = start_pos.min(end_pos);
+        let num_stripes = (end_pos - start_pos).div_ceil(stripe.stripe_size);
+
+        let mut workers = Vec::with_capacity(stripe.num_threads);
+        for thread_idx in 0..stripe.num_threads {
+            let stripes = (thread_idx as u64..num_stripes)
+                .step_by(stripe.num_threads)
+                .map(|i| {
+                    let start = start_pos + i * stripe.stripe_size;
+                    (start, (start + stripe.stripe_size).min(end_pos))
+                })
+                .collect::<VecDeque<_>>();
+            let (data_tx, data) = mpsc::sync_channel(config.num_buffer);
+            let (free, free_rx) = mpsc::sync_channel(config.num_buffer);
+            let (fpath, config) = (fpath.to_string(), config.clone());
+            let handle = thread::Builder::new()
+                .name(format!("fiox-read-{}", thread_idx))
+                .spawn(move || {
+                    let res = StripeReader::new(&fpath, stripes, config)
+                        .and_then(|mut reader| reader.run(&data_tx, &free_rx));
+                    if let Err(e) = res {
+                        let _ = data_tx.send(Err(e));
+                    }
+                })
+                .context("spawn reading thread failed")?;
+            workers.push(Worker { data, free, handle });
+        }
+
+        Ok(Self {
+            workers,
+            start_pos,
+            end_pos,
+            stripe_size: stripe.stripe_size,
+            stripe: 0,
+            stripe_left: (end_pos - start_pos).min(stripe.stripe_size),
+            chunk: None,
+        })
+    }
+
+    /// fill `buf`, less than `buf.len()` only at `end_pos`
+    pub fn read2buf(&mut self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let data = self.fill_buf()?;
+            if data.is_empty() {
+                break;
+            }
+            let n = (buf.len() - filled).min(data.len());
+            buf[filled..filled + n].copy_from_slice(&data[..n]);
+            filled += n;
+            self.consume(n);
+        }
+        Ok(filled)
+    }
+
+    /// the next data in file order, a slice of a reading thread's buffer. empty at `end_pos`.
+    /// `consume` marks it as read
+    pub fn fill_buf(&mut self) -> anyhow::Result<&[u8]> {
+        let drained = match &self.chunk {
+            Some((chunk, pos)) => *pos == chunk.data().len(),
+            None => true,
+        };
+        if drained && !self.next_chunk()? {
+            return Ok(&[]);
+        }
+        match &self.chunk {
+            Some((chunk, pos)) => Ok(&chunk.data()[*pos..]),
+            None => Ok(&[]),
+        }
+    }
+
+    /// `amt` bytes of the slice returned by `fill_buf` are used
+    pub fn consume(&mut self, amt: usize) {
+        if let Some((chunk, pos)) = self.chunk.as_mut() {
+            *pos += amt;
+            assert!(*pos <= chunk.data().len());
+        }
+    }
+
+    /// receive the next chunk in file order and give the drained one back, false at `end_pos`
+    fn next_chunk(&mut self) -> anyhow::Result<bool> {
+        if let Some((drained, _)) = self.chunk.take() {
+            let worker = &self.workers[(self.stripe % self.workers.len() as u64) as usize];
+            // the thread may be gone after an error, the buffer is dropped then
+            let _ = worker.free.try_send(drained);
+        }
+        if self.stripe_left == 0 {
+            self.stripe += 1;
+            let start = self.start_pos + self.stripe * self.stripe_size;
+            if start >= self.end_pos {
+                return Ok(false);
+            }
+            self.stripe_left = (self.end_pos - start).min(self.stripe_size);
+        }
+        let worker = &self.workers[(self.stripe % self.workers.len() as u64) as usize];
+        let chunk = match worker.data.recv() {
+            Ok(chunk) => chunk?,
+            Err(_) => anyhow::bail!("reading thread of stripe {} exited", self.stripe),
+        };
+        self.stripe_left -= chunk.data().len() as u64;
+        self.chunk = Some((chunk, 0));
+        Ok(true)
+    }
+}
+
+impl Drop for ParallelSequentialReader {
+    fn drop(&mut self) {
+        // closing the channels stops the threads blocked on sending
+        let handles = std::mem::take(&mut self.workers)
+            .into_iter()
+            .map(|worker| worker.handle)
+            .collect::<Vec<_>>();
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// where the data of a read lands in its buffer
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    pos: u64,     // the aligned file position of the buffer
+    begin: usize, // the data in the buffer
+    end: usize,
+}
+
+/// the reading side of a thread: one ring with its buffers registered, moved from stripe to stripe
+struct StripeReader {
+    #[allow(unused)]
+    file: fs::File, // keeps the registered fd open
+    fpath: String,
+    ring: StreamRing,
+    buffers: Vec<Option<Buffer>>, // `None` while the consumer has it, must be dropped after `ring`
+    slots: Vec<Slot>,
+    order: VecDeque<usize>, // the buffers read into, in file order
+    stripes: VecDeque<(u64, u64)>,
+    cursor: u64, // the position to read next in the first stripe
+}
+
+impl StripeReader {
+    fn new(
+        fpath: &str,
+        stripes: VecDeque<(u64, u64)>,
+        config: ReaderConfig,
+    ) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(fpath)
+            .with_context(|| format!("open {} failed", fpath))?;
+        lock::lock(&file, fpath, false, config.lock)?;
+        let mut buffers = stream_buffers(
+            config.buffer_size,
+            config.num_buffer,
+            config.pool.as_ref(),
+            config.alloc,
+        )?;
+        // a request linked to its timeout takes two entries
+        let entries = if config.timeout.is_some() {
+            2 * config.num_buffer
+        } else {
+            config.num_buffer
+        };
+        let mut ring = StreamRing::private(entries as u32, file.as_raw_fd(), &mut buffers)?;
+        ring.set_timeout(config.timeout);
+        Ok(Self {
+            file,
+            fpath: fpath.to_string(),
+            ring,
+            slots: vec![Slot::default(); buffers.len()],
+            buffers: buffers.into_iter().map(Some).collect(),
+            order: VecDeque::new(),
+            cursor: stripes.front().map_or(0, |&(start, _)| start),
+            stripes,
+        })
+    }
+
+    /// read the stripes into the free buffers and send them in order until all are sent
+    /// or the consumer is gone
+    fn run(
+        &mut self,
+        data: &SyncSender<anyhow::Result<Chunk>>,
+        free: &Receiver<Chunk>,
+    ) -> anyhow::Result<()> {
+        loop {
+            while let Ok(chunk) = free.try_recv() {
+                self.buffers[chunk.buf_idx] = Some(chunk.buf);
+            }
+            self.submit_reads()?;
+
+            let Some(&idx) = self.order.front() else {
+                if self.stripes.is_empty() {
+                    return Ok(());
+                }
+                // all buffers are with the consumer
+                match free.recv() {
+                    Ok(chunk) => self.buffers[chunk.buf_idx] = Some(chunk.buf),
+                    Err(_) => return Ok(()),
+                }
+                continue;
+            };
+            let slot = self.slots[idx];
+            let buf = self.buffers[idx].as_ref().expect("buffer being read");
+            if buf.len < slot.end {
+                let cqe = self.ring.wait()?;
+                self.complete(cqe.user_data as usize, cqe.into_result())?;
+                continue;
+            }
+            self.order.pop_front();
+            let chunk = Chunk {
+                buf_idx: idx,
+                buf: self.buffers[idx].take().expect("buffer being read"),
+                begin: slot.begin,
+                end: slot.end,
+            };
+            if data.send(Ok(chunk)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// start a read into every free buffer, moving on to the next stripe at the end of one
+    fn submit_reads(&mut self) -> anyhow::Result<()> {
+        let page_size = get_page_size() as u64;
+        for idx in 0..self.buffers.len() {
+            let Some(&(_, stripe_end)) = self.stripes.front() else {
+                return Ok(());
+            };
+            let cap = match self.buffers[idx].as_mut() {
+                Some(buf) if !self.order.contains(&idx) => {
+                    buf.len = 0;
+                    buf.cap()
+                }
+                _ => continue,
+            };
+            let pos = self.cursor - self.cursor % page_size;
+            let begin = (self.cursor - pos) as usize;
+            let end = (stripe_end - pos).min(cap as u64) as usize;
+            self.slots[idx] = Slot { pos, begin, end };
+            self.order.push_back(idx);
+            self.push_read(idx)?;
+
+            self.cursor = pos + end as u64;
+            if self.cursor == stripe_end {
+                self.stripes.pop_front();
+                if let Some(&(start, _)) = self.stripes.front() {
+                    self.cursor = start;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self, idx: usize, res: std::io::Result<u32>) -> anyhow::Result<()> {
+        let slot = self.slots[idx];
+        let buf = self.buffers[idx].as_mut().expect("buffer being read");
+        let at = slot.pos + buf.len as u64;
+        let n = res.with_context(|| format!("read {} at {} failed", self.fpath, at))?;
+        if n == 0 {
+            anyhow::bail!("unexpected eof reading {} at {}", self.fpath, at);
+        }
+        buf.len += n as usize;
+        if buf.len < slot.end {
+            // short read. O_DIRECT needs an aligned offset, so the unaligned part is read again
+            buf.len -= buf.len % get_page_size();
+            self.push_read(idx)?;
+        }
+        Ok(())
+    }
+
+    /// read the part of the buffer that is not filled yet, up to the page after its data
+    fn push_read(&mut self, idx: usize) -> anyhow::Result<()> {
+        let page_size = get_page_size();
+        let slot = self.slots[idx];
+        let buf = self.buffers[idx].as_mut().expect("buffer being read");
+        let filled = buf.len;
+        let len = slot.end.div_ceil(page_size) * page_size - filled;
+        let sqe = io_uring::opcode::ReadFixed::new(
+            self.ring.file(),
+            unsafe { buf.as_mut_ptr().add(filled) },
+            len as u32,
+            self.ring.buf_index(idx),
+        )
+        .offset(slot.pos + filled as u64)
+        .build()
+        .user_data(idx as u64);
+
+        unsafe { self.ring.push(sqe) }
+    }
+}
@@ -0,0 +1,324 @@
+#![cfg(target_os = "linux")]
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    os::unix::fs::FileExt,
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::Context;
+
+use super::{
+    buffer::{AllocOptions, Buffer},
+    direct_file::{DirectFile, OpHandle},
+    lock,
+    parallel_reader::StripeConfig,
+    sequential_writer::{Durability, Preallocation, WriterConfig},
+    utils::get_page_size,
+};
+
+/// a writing thread, it writes the buffers of its stripes in the order they come
+struct Worker {
+    data: Option<SyncSender<(u64, Buffer)>>, // closed by `finish`
+    handle: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+/// writes a file from `start_pos` on with several threads, one ring each. the data is taken in
+/// order from one producer and cut into stripes dealt round robin to the threads, the unaligned
+/// tail is written and the file truncated by `finish` once every stripe is written.
+pub struct ParallelSequentialWriter {
+    file: fs::File,
+    fpath: String,
+    workers: Vec<Worker>,
+    free: Receiver<Buffer>, // buffers written by the threads
+    buffer_size: usize,
+    alloc: AllocOptions,
+    start_pos: u64,
+    stripe_size: u64,
+    buf_pos: u64, // file position of `buf`
+    buf: Option<Buffer>,
+    initial_size: u64, // `finish` never cuts the file below this
+    durability: Durability,
+    locked: bool,
+    finished: bool,
+}
+
+impl ParallelSequentialWriter {
+    /// `start_pos` must be a multiple of the page size
+    pub fn new(
+        fpath: &str,
+        start_pos: u64,
+        stripe: StripeConfig,
+        buffer_size: usize,
+        num_buffer: usize,
+    ) -> anyhow::Result<Self> {
+        Self::with_config(
+            fpath,
+            start_pos,
+            stripe,
+            WriterConfig::new(buffer_size, num_buffer),
+        )
+    }
+
+    /// every thread writes with `num_buffer` requests in flight. `pool`, `timeout` and
+    /// `preallocation` are not supported, `durability` syncs once on `finish` unless `None`
+    pub fn with_config(
+        fpath: &str,
+        start_pos: u64,
+        stripe: StripeConfig,
+        config: WriterConfig,
+    ) -> anyhow::Result<Self> {
+        let WriterConfig {
+            buffer_size,
+            num_buffer,
+            pool,
+            alloc,
+            timeout,
+            durability,
+            preallocation,
+            lock,
+        } = config;
+        stripe.check()?;
+        if pool.is_some() || timeout.is_some() || preallocation != Preallocation::None {
+            anyhow::bail!(
+                "ParallelSequentialWriter supports neither pool, timeout nor preallocation"
+            );
+        }
+        let page_size = get_page_size();
+        if buffer_size == 0 || !buffer_size.is_multiple_of(page_size) {
+            anyhow::bail!(
+                "buffer_size {} is not a multiple of the page size",
+                buffer_size
+            );
+        }
+        if !start_pos.is_multiple_of(page_size as u64) {
+            anyhow::bail!("start_pos {} is not a multiple of the page size", start_pos);
+        }
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(fpath)
+            .with_context(|| format!("open {} failed", fpath))?;
+        lock::lock(&file, fpath, true, lock)?;
+        let initial_size = file
+            .metadata()
+            .with_context(|| format!("stat {} failed", fpath))?
+            .len();
+
+        let (free_tx, free) = mpsc::channel();
+        let mut workers = Vec::with_capacity(stripe.num_threads);
+        for thread_idx in 0..stripe.num_threads {
+            let direct =
+                DirectFile::with_options(fpath, OpenOptions::new().write(true), num_buffer)?;
+            let (data_tx, data_rx) = mpsc::sync_channel(num_buffer);
+            let free_tx = free_tx.clone();
+            let fpath = fpath.to_string();
+            let handle = thread::Builder::new()
+                .name(format!("fiox-write-{}", thread_idx))
+                .spawn(move || write_stripes(direct, &fpath, num_buffer, data_rx, free_tx))
+                .context("spawn writing thread failed")?;
+            workers.push(Worker {
+                data: Some(data_tx),
+                handle: Some(handle),
+            });
+        }
+
+        Ok(Self {
+            file,
+            fpath: fpath.to_string(),
+            workers,
+            free,
+            buffer_size,
+            alloc,
+            start_pos,
+            stripe_size: stripe.stripe_size,
+            buf_pos: start_pos,
+            buf: None,
+            initial_size,
+            durability,
+            locked: lock != lock::LockMode::None,
+            finished: false,
+        })
+    }
+
+    pub fn write(&mut self, mut data: &[u8]) -> anyhow::Result<()> {
+        if self.finished {
+            anyhow::bail!("{} is finished", self.fpath);
+        }
+        while !data.is_empty() {
+            let mut buf = match self.buf.take() {
+                Some(buf) => buf,
+                None => self.new_buffer()?,
+            };
+            let filled = buf.len;
+            // a buffer never crosses a stripe boundary
+            let stripe_left = self.stripe_size - (self.buf_pos - self.start_pos) % self.stripe_size;
+            let room = (self.buffer_size - filled).min(stripe_left as usize - filled);
+            let n = room.min(data.len());
+            buf[filled..filled + n].copy_from_slice(&data[..n]);
+            buf.len += n;
+            data = &data[n..];
+            if n == room {
+                self.dispatch(buf)?;
+            } else {
+                self.buf = Some(buf);
+            }
+        }
+        Ok(())
+    }
+
+    /// wait for every thread, write the unaligned tail and truncate the padding of it.
+    /// the writer must not be used after this returns.
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let mut tail = None;
+        let mut end = self.buf_pos;
+        if let Some(mut buf) = self.buf.take() {
+            end += buf.len as u64;
+            if self.initial_size <= end {
+                // no old data behind the tail that its padding would overwrite
+                let filled = buf.len;
+                let padded = filled.next_multiple_of(get_page_size());
+                buf[filled..padded].fill(0);
+                buf.len = padded;
+                self.dispatch(buf)?;
+            } else {
+                tail = Some((self.buf_pos, buf));
+            }
+        }
+        self.join()?;
+
+        if let Some((pos, buf)) = tail {
+            self.file
+                .write_all_at(&buf[..buf.len], pos)
+                .with_context(|| format!("write tail of {} failed", self.fpath))?;
+        }
+        self.file
+            .set_len(self.initial_size.max(end))
+            .with_context(|| format!("truncate {} failed", self.fpath))?;
+        if self.durability != Durability::None {
+            self.file
+                .sync_data()
+                .with_context(|| format!("fdatasync {} failed", self.fpath))?;
+        }
+        if self.locked {
+            self.locked = false;
+            lock::unlock(&self.file, &self.fpath)?;
+        }
+        Ok(())
+    }
+
+    fn new_buffer(&mut self) -> anyhow::Result<Buffer> {
+        let mut buf = match self.free.try_recv() {
+            Ok(buf) => buf,
+            Err(_) => Buffer::with_options(self.buffer_size, get_page_size(), self.alloc)?,
+        };
+        buf.len = 0;
+        Ok(buf)
+    }
+
+    /// hand a full buffer, or the padded tail, to the thread of its stripe
+    fn dispatch(&mut self, buf: Buffer) -> anyhow::Result<()> {
+        let pos = self.buf_pos;
+        self.buf_pos += buf.len as u64;
+        let stripe = (pos - self.start_pos) / self.stripe_size;
+        let idx = (stripe % self.workers.len() as u64) as usize;
+        let sent = match self.workers[idx].data.as_ref() {
+            Some(data) => data.send((pos, buf)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            // the thread stopped on an error
+            self.finished = true;
+            self.join()?;
+            anyhow::bail!("writing thread of stripe {} exited", stripe);
+        }
+        Ok(())
+    }
+
+    /// close the channels and wait for the threads, the first error is returned
+    fn join(&mut self) -> anyhow::Result<()> {
+        for worker in self.workers.iter_mut() {
+            worker.data = None;
+        }
+        let mut res = Ok(());
+        for worker in self.workers.iter_mut() {
+            let Some(handle) = worker.handle.take() else {
+                continue;
+            };
+            let out = match handle.join() {
+                Ok(out) => out,
+                Err(_) => Err(anyhow::anyhow!("writing thread of {} panicked", self.fpath)),
+            };
+            if res.is_ok() {
+                res = out;
+            }
+        }
+        res
+    }
+}
+
+/// finishes the writer, an error is discarded. call `finish` to see it.
+impl Drop for ParallelSequentialWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// the body of a writing thread, up to `queue_depth` writes are in flight
+fn write_stripes(
+    mut file: DirectFile,
+    fpath: &str,
+    queue_depth: usize,
+    data: Receiver<(u64, Buffer)>,
+    free: Sender<Buffer>,
+) -> anyhow::Result<()> {
+    let mut in_flight = VecDeque::with_capacity(queue_depth);
+    for (pos, buf) in data {
+        if in_flight.len() == queue_depth {
+            let (handle, pos) = in_flight.pop_front().unwrap();
+            complete(&mut file, fpath, handle, pos, &free)?;
+        }
+        in_flight.push_back((file.write_at(buf, pos)?, pos));
+    }
+    while let Some((handle, pos)) = in_flight.pop_front() {
+        complete(&mut file, fpath, handle, pos, &free)?;
+    }
+    Ok(())
+}
+
+/// wait for a write and write the rest of a short one again
+fn complete(
+    file: &mut DirectFile,
+    fpath: &str,
+    mut handle: OpHandle,
+    mut pos: u64,
+    free: &Sender<Buffer>,
+) -> anyhow::Result<()> {
+    loop {
+        let out = file.wait(handle)?;
+        let mut buf = out.buf.unwrap();
+        if out.len == 0 {
+            anyhow::bail!("write {} at {} wrote nothing", fpath, pos);
+        }
+        if out.len >= buf.len {
+            // the writer may be gone already
+            let _ = free.send(buf);
+            return Ok(());
+        }
+        // O_DIRECT needs an aligned offset, so the unaligned part is written again
+        let done = out.len - out.len % get_page_size();
+        let len = buf.len;
+        buf.copy_within(done..len, 0);
+        buf.len = len - done;
+        pos += done as u64;
+        handle = file.write_at(buf, pos)?;
+    }
+}
@@ -0,0 +1,225 @@
+#![cfg(target_os = "linux")]
+//! batches of page aligned positional requests, shared by `RandomReader` and `RandomWriter`
+
+use anyhow::Context;
+
+use super::{
+    buffer::{AlignedVecU8, AllocOptions, Buffer},
+    buffer_pool::BufferPool,
+    io_context::StreamRing,
+    utils::get_page_size,
+};
+
+/// the largest single request, longer spans are split
+const MAX_IO_SIZE: usize = 1 << 20;
+
+/// a merged, page aligned part of the file
+pub(crate) struct Span {
+    pub start: u64,
+    pub len: usize,
+    pub data: AlignedVecU8, // at least `len` bytes
+    pub fixed: Option<u16>, // registered buffer index of `data`
+}
+
+/// one request on a span, `done` bytes of it are transferred
+pub(crate) struct Piece {
+    pub span: usize,
+    pub offset: usize, // in the span
+    pub len: usize,
+    pub done: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Read,
+    Write,
+}
+
+/// align `(offset, len)` ranges to pages and merge the overlapping and adjacent ones.
+/// returns the `[start, end)` of the spans and the span of every range, empty ranges get span 0.
+pub(crate) fn coalesce(ranges: &[(u64, usize)]) -> (Vec<(u64, u64)>, Vec<usize>) {
+    let page_size = get_page_size() as u64;
+    let mut order = (0..ranges.len())
+        .filter(|&i| ranges[i].1 > 0)
+        .collect::<Vec<_>>();
+    order.sort_by_key(|&i| ranges[i].0);
+
+    let mut bounds: Vec<(u64, u64)> = vec![];
+    let mut span_of = vec![0; ranges.len()];
+    for &i in order.iter() {
+        let (offset, len) = ranges[i];
+        let start = offset - offset % page_size;
+        let end = (offset + len as u64).div_ceil(page_size) * page_size;
+        match bounds.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => bounds.push((start, end)),
+        }
+        span_of[i] = bounds.len() - 1;
+    }
+    (bounds, span_of)
+}
+
+/// uninitialized memory for the spans
+pub(crate) fn alloc_spans(bounds: &[(u64, u64)]) -> anyhow::Result<Vec<Span>> {
+    bounds.iter().map(|&bound| alloc_span(bound)).collect()
+}
+
+fn alloc_span((start, end): (u64, u64)) -> anyhow::Result<Span> {
+    let len = (end - start) as usize;
+    let data = AlignedVecU8::with_options(len, get_page_size(), AllocOptions::default())?;
+    Ok(Span {
+        start,
+        len,
+        data,
+        fixed: None,
+    })
+}
+
+/// the spans that fit a buffer of `pool` get a free one, registered at `fixed[slot]`,
+/// the others are allocated
+pub(crate) fn pool_spans(
+    bounds: &[(u64, u64)],
+    pool: &BufferPool,
+    fixed: &[u16],
+) -> anyhow::Result<Vec<Span>> {
+    bounds
+        .iter()
+        .map(|&(start, end)| {
+            let len = (end - start) as usize;
+            if len > pool.buffer_size() {
+                return alloc_span((start, end));
+            }
+            let Some(buf) = pool.try_acquire(1)?.and_then(|mut bufs| bufs.pop()) else {
+                return alloc_span((start, end));
+            };
+            let slot = pool.slot_of(&buf).expect("buffer of the pool");
+            let Buffer { data, .. } = buf;
+            Ok(Span {
+                start,
+                len,
+                data,
+                fixed: Some(fixed[slot]),
+            })
+        })
+        .collect()
+}
+
+/// cover the spans whole, in requests of at most `MAX_IO_SIZE`
+pub(crate) fn split(spans: &[Span]) -> Vec<Piece> {
+    let mut pieces = vec![];
+    for (idx, span) in spans.iter().enumerate() {
+        let mut offset = 0;
+        while offset < span.len {
+            let len = MAX_IO_SIZE.min(span.len - offset);
+            pieces.push(Piece {
+                span: idx,
+                offset,
+                len,
+                done: 0,
+            });
+            offset += len;
+        }
+    }
+    pieces
+}
+
+/// run all `pieces` with at most `queue_depth` in flight, resubmitting short transfers.
+/// reads stop at `file_size`. whatever happens nothing is in flight when this returns,
+/// so the spans can be freed.
+pub(crate) fn run(
+    ring: &mut StreamRing,
+    queue_depth: usize,
+    op: Op,
+    fpath: &str,
+    file_size: u64,
+    spans: &mut [Span],
+    pieces: &mut [Piece],
+) -> anyhow::Result<()> {
+    let res = run_inner(ring, queue_depth, op, fpath, file_size, spans, pieces);
+    if res.is_err() {
+        // the kernel may still use the spans
+        while ring.in_flight() > 0 {
+            if ring.wait().is_err() {
+                break;
+            }
+        }
+    }
+    res
+}
+
+fn run_inner(
+    ring: &mut StreamRing,
+    queue_depth: usize,
+    op: Op,
+    fpath: &str,
+    file_size: u64,
+    spans: &mut [Span],
+    pieces: &mut [Piece],
+) -> anyhow::Result<()> {
+    let verb = match op {
+        Op::Read => "read",
+        Op::Write => "write",
+    };
+    let mut next = 0;
+    while next < pieces.len() || ring.in_flight() > 0 {
+        while next < pieces.len() && ring.in_flight() < queue_depth {
+            push(ring, op, spans, pieces, next)?;
+            next += 1;
+        }
+
+        let cqe = ring.wait()?;
+        let idx = cqe.user_data as usize;
+        let piece = &mut pieces[idx];
+        let pos = spans[piece.span].start + (piece.offset + piece.done) as u64;
+        let n = cqe
+            .into_result()
+            .with_context(|| format!("{} {} at {} failed", verb, fpath, pos))?;
+        if n == 0 {
+            if op == Op::Read && pos >= file_size {
+                // the aligned end of the last span is past the end of the file
+                continue;
+            }
+            anyhow::bail!("{} {} at {} made no progress", verb, fpath, pos);
+        }
+        piece.done += n as usize;
+        if piece.done < piece.len && (op == Op::Write || pos + (n as u64) < file_size) {
+            // short transfer. O_DIRECT needs an aligned offset, so the unaligned part is done again
+            piece.done -= piece.done % get_page_size();
+            push(ring, op, spans, pieces, idx)?;
+        }
+    }
+    Ok(())
+}
+
+fn push(
+    ring: &mut StreamRing,
+    op: Op,
+    spans: &mut [Span],
+    pieces: &[Piece],
+    idx: usize,
+) -> anyhow::Result<()> {
+    let piece = &pieces[idx];
+    let span = &mut spans[piece.span];
+    let from = piece.offset + piece.done;
+    let ptr = unsafe { span.data.as_mut_ptr().add(from) };
+    let len = (piece.len - piece.done) as u32;
+    let pos = span.start + from as u64;
+    let sqe = match (op, span.fixed) {
+        (Op::Read, Some(index)) => io_uring::opcode::ReadFixed::new(ring.file(), ptr, len, index)
+            .offset(pos)
+            .build(),
+        (Op::Read, None) => io_uring::opcode::Read::new(ring.file(), ptr, len)
+            .offset(pos)
+            .build(),
+        (Op::Write, Some(index)) => io_uring::opcode::WriteFixed::new(ring.file(), ptr, len, index)
+            .offset(pos)
+            .build(),
+        (Op::Write, None) => io_uring::opcode::Write::new(ring.file(), ptr, len)
+            .offset(pos)
+            .build(),
+    }
+    .user_data(idx as u64);
+
+    // `run` waits for everything in flight before the spans can go away
+    unsafe { ring.push(sqe) }
+}
@@ -0,0 +1,146 @@
+#![cfg(target_os = "linux")]
+use std::{
+    fs::{self, OpenOptions},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+use anyhow::Context;
+use bytes::Bytes;
+
+use super::{
+    buffer_pool::BufferPool,
+    io_context::{IoContext, StreamRing},
+    positional::{Op, alloc_spans, coalesce, pool_spans, run, split},
+};
+
+/// scattered reads of one file in batches.
+///
+/// the ranges of a `read_many` call are aligned to pages for O_DIRECT, overlapping and adjacent
+/// ones are merged into one span, and all spans are read in one io_uring batch.
+/// the returned `Bytes` are views into the memory of their span, nothing is copied.
+pub struct RandomReader {
+    #[allow(unused)]
+    file: fs::File, // keeps the registered fd open
+    fpath: String,
+    file_size: u64,
+    ring: StreamRing,
+    queue_depth: usize,
+    pool: Option<BufferPool>, // its buffers are registered with the ring
+    fixed: Vec<u16>,          // registered buffer index of each slot of `pool`
+}
+
+impl RandomReader {
+    /// `queue_depth`: the most reads in flight at once
+    pub fn new(fpath: &str, queue_depth: usize) -> anyhow::Result<Self> {
+        Self::new_in(None, fpath, queue_depth, None)
+    }
+
+    /// `new` with the buffers of `pool` registered with the ring. the spans that fit a buffer
+    /// are read into a free one with `ReadFixed` instead of allocated memory, a buffer goes back
+    /// to the pool once every `Bytes` of its span is dropped. waits until every buffer of the pool
+    /// is free to register it
+    pub fn with_pool(fpath: &str, queue_depth: usize, pool: BufferPool) -> anyhow::Result<Self> {
+        Self::new_in(None, fpath, queue_depth, Some(pool))
+    }
+
+    /// `ctx`: the shared ring to submit to, a private ring is created if `None`
+    pub(crate) fn new_in(
+        ctx: Option<&IoContext>,
+        fpath: &str,
+        queue_depth: usize,
+        pool: Option<BufferPool>,
+    ) -> anyhow::Result<Self> {
+        if queue_depth == 0 {
+            anyhow::bail!("queue_depth must be at least 1");
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(fpath)
+            .with_context(|| format!("open {} failed", fpath))?;
+        let file_size = file
+            .metadata()
+            .with_context(|| format!("stat {} failed", fpath))?
+            .len();
+        // pool buffers keep their memory and slot while the pool lives, so they stay registered
+        let mut buffers = match pool.as_ref() {
+            Some(pool) => pool.acquire(pool.capacity())?,
+            None => vec![],
+        };
+        let ring = match ctx {
+            Some(ctx) => ctx.open_stream(Some(file.as_raw_fd()), &mut buffers)?,
+            None => StreamRing::private(queue_depth as u32, file.as_raw_fd(), &mut buffers)?,
+        };
+        let mut fixed = vec![0; buffers.len()];
+        if let Some(pool) = pool.as_ref() {
+            for (i, buf) in buffers.iter().enumerate() {
+                let slot = pool.slot_of(buf).expect("buffer of the pool");
+                fixed[slot] = ring.buf_index(i);
+            }
+        }
+        Ok(Self {
+            file,
+            fpath: fpath.to_string(),
+            file_size,
+            ring,
+            queue_depth,
+            pool,
+            fixed,
+        })
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// read all `(offset, len)` ranges, the result is in the order of `ranges`
+    pub fn read_many(&mut self, ranges: &[(u64, usize)]) -> anyhow::Result<Vec<Bytes>> {
+        for &(offset, len) in ranges {
+            if offset
+                .checked_add(len as u64)
+                .is_none_or(|end| end > self.file_size)
+            {
+                anyhow::bail!(
+                    "range {}+{} is beyond the end of {} ({})",
+                    offset,
+                    len,
+                    self.fpath,
+                    self.file_size
+                );
+            }
+        }
+
+        let (bounds, span_of) = coalesce(ranges);
+        let mut spans = match self.pool.as_ref() {
+            Some(pool) => pool_spans(&bounds, pool, &self.fixed)?,
+            None => alloc_spans(&bounds)?,
+        };
+        let mut pieces = split(&spans);
+        run(
+            &mut self.ring,
+            self.queue_depth,
+            Op::Read,
+            &self.fpath,
+            self.file_size,
+            &mut spans,
+            &mut pieces,
+        )?;
+
+        let spans = spans
+            .into_iter()
+            .map(|span| (span.start, Bytes::from_owner(span.data)))
+            .collect::<Vec<_>>();
+        Ok(ranges
+            .iter()
+            .zip(span_of)
+            .map(|(&(offset, len), idx)| {
+                if len == 0 {
+                    return Bytes::new();
+                }
+                let (start, data) = &spans[idx];
+                let begin = (offset - start) as usize;
+                data.slice(begin..begin + len)
+            })
+            .collect())
+    }
+}
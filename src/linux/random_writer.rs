@@ -0,0 +1,178 @@
+#![cfg(target_os = "linux")]
+use std::{
+    fs::{self, OpenOptions},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+use anyhow::Context;
+
+use super::{
+    io_context::{IoContext, StreamRing},
+    positional::{Op, Piece, alloc_spans, coalesce, run, split},
+    utils::get_page_size,
+};
+
+/// scattered in place writes of one file in batches.
+///
+/// the writes of a `write_many` call are aligned to pages for O_DIRECT and merged like the ranges
+/// of `RandomReader::read_many`. pages a write only partly covers are read first, so the bytes
+/// around it are kept. the file grows to the end of the last write, never to the aligned end.
+pub struct RandomWriter {
+    file: fs::File,
+    fpath: String,
+    file_size: u64,
+    ring: StreamRing,
+    queue_depth: usize,
+}
+
+impl RandomWriter {
+    /// `queue_depth`: the most requests in flight at once. the file is created if missing
+    pub fn new(fpath: &str, queue_depth: usize) -> anyhow::Result<Self> {
+        Self::new_in(None, fpath, queue_depth)
+    }
+
+    /// `ctx`: the shared ring to submit to, a private ring is created if `None`
+    pub(crate) fn new_in(
+        ctx: Option<&IoContext>,
+        fpath: &str,
+        queue_depth: usize,
+    ) -> anyhow::Result<Self> {
+        if queue_depth == 0 {
+            anyhow::bail!("queue_depth must be at least 1");
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .custom_flags(libc::O_DIRECT)
+            .open(fpath)
+            .with_context(|| format!("open {} failed", fpath))?;
+        let file_size = file
+            .metadata()
+            .with_context(|| format!("stat {} failed", fpath))?
+            .len();
+        let ring = match ctx {
+            Some(ctx) => ctx.open_stream(Some(file.as_raw_fd()), &mut [])?,
+            None => StreamRing::private(queue_depth as u32, file.as_raw_fd(), &mut [])?,
+        };
+        Ok(Self {
+            file,
+            fpath: fpath.to_string(),
+            file_size,
+            ring,
+            queue_depth,
+        })
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// write all `(offset, data)` pairs, where writes overlap the later one wins
+    pub fn write_many(&mut self, writes: &[(u64, &[u8])]) -> anyhow::Result<()> {
+        let ranges = writes
+            .iter()
+            .map(|&(offset, data)| (offset, data.len()))
+            .collect::<Vec<_>>();
+        let (bounds, span_of) = coalesce(&ranges);
+        let mut spans = alloc_spans(&bounds)?;
+
+        // the edge pages of every write that it does not cover whole
+        let page_size = get_page_size();
+        let mut edges = vec![];
+        for (&(offset, len), &idx) in ranges.iter().zip(span_of.iter()) {
+            if len == 0 {
+                continue;
+            }
+            let begin = (offset - spans[idx].start) as usize;
+            let end = begin + len;
+            if !begin.is_multiple_of(page_size) {
+                edges.push((idx, begin - begin % page_size));
+            }
+            if !end.is_multiple_of(page_size) {
+                edges.push((idx, end - end % page_size));
+            }
+        }
+        edges.sort_unstable();
+        edges.dedup();
+
+        // pages past the end of the file are not read, their gaps are written as zeros
+        let mut pieces = vec![];
+        for &(idx, offset) in edges.iter() {
+            let span = &mut spans[idx];
+            span.data[offset..offset + page_size].fill(0);
+            if span.start + (offset as u64) < self.file_size {
+                pieces.push(Piece {
+                    span: idx,
+                    offset,
+                    len: page_size,
+                    done: 0,
+                });
+            }
+        }
+        run(
+            &mut self.ring,
+            self.queue_depth,
+            Op::Read,
+            &self.fpath,
+            self.file_size,
+            &mut spans,
+            &mut pieces,
+        )?;
+
+        for (&(offset, data), &idx) in writes.iter().zip(span_of.iter()) {
+            if data.is_empty() {
+                continue;
+            }
+            let begin = (offset - spans[idx].start) as usize;
+            spans[idx].data[begin..begin + data.len()].copy_from_slice(data);
+        }
+
+        let mut pieces = split(&spans);
+        run(
+            &mut self.ring,
+            self.queue_depth,
+            Op::Write,
+            &self.fpath,
+            self.file_size,
+            &mut spans,
+            &mut pieces,
+        )?;
+
+        // the last span was written up to its aligned end
+        let written_end = bounds.last().map_or(0, |&(_, end)| end);
+        if written_end > self.file_size {
+            let new_size = ranges
+                .iter()
+                .map(|&(offset, len)| offset + len as u64)
+                .max()
+                .unwrap_or(0)
+                .max(self.file_size);
+            self.file
+                .set_len(new_size)
+                .with_context(|| format!("truncate {} to {} failed", self.fpath, new_size))?;
+            self.file_size = new_size;
+        }
+        Ok(())
+    }
+
+    /// `write_many`, then an fsync of the file once the whole batch is written
+    pub fn write_many_and_sync(&mut self, writes: &[(u64, &[u8])]) -> anyhow::Result<()> {
+        self.write_many(writes)?;
+        self.sync()
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        let sqe = io_uring::opcode::Fsync::new(self.ring.file())
+            .build()
+            .user_data(0);
+        // no memory is shared with the kernel
+        unsafe { self.ring.push(sqe)? };
+        self.ring
+            .wait()?
+            .into_result()
+            .with_context(|| format!("fsync {} failed", self.fpath))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+#![cfg(target_os = "linux")]
+use std::{
+    fs::{self, OpenOptions},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+use anyhow::Context;
+
+use super::{
+    buffer::Buffer,
+    buffer_pool::stream_buffers,
+    io_context::{IoContext, StreamRing},
+    lock,
+    sequential_reader::ReaderConfig,
+    utils::get_page_size,
+};
+
+/// reads `[start_pos, end_pos)` of a file from the end to the start, for `tail -n` or finding the
+/// last valid record of a log. the file is read in `buffer_size` aligned blocks, the blocks before
+/// the one handed out are read ahead into the other buffers.
+pub struct ReverseReader {
+    #[allow(unused)]
+    file: fs::File, // keeps the registered fd open
+    fpath: String,
+    buffer_size: usize,
+    ring: StreamRing,
+    buffers: Vec<Buffer>,
+    start_pos: u64,
+    end_pos: u64,
+    num_blocks: u64,
+    next_block: u64,  // the block to hand out next, 0 is the last one of the file
+    next_submit: u64, // the block to read next
+    handed_out: bool, // the buffer of `next_block - 1` is still borrowed by the caller
+}
+
+impl ReverseReader {
+    /// `end_pos`: the end of the file if `None`
+    pub fn new(
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        buffer_size: usize,
+        num_buffer: usize,
+    ) -> anyhow::Result<Self> {
+        Self::with_config(
+            fpath,
+            start_pos,
+            end_pos,
+            ReaderConfig::new(buffer_size, num_buffer),
+        )
+    }
+
+    /// `provided_buffers` and `follow` of the config are not supported
+    pub fn with_config(
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        config: ReaderConfig,
+    ) -> anyhow::Result<Self> {
+        Self::new_in(None, fpath, start_pos, end_pos, config)
+    }
+
+    /// `ctx`: the shared ring to submit to, a private ring is created if `None`
+    pub(crate) fn new_in(
+        ctx: Option<&IoContext>,
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        config: ReaderConfig,
+    ) -> anyhow::Result<Self> {
+        let ReaderConfig {
+            buffer_size,
+            num_buffer,
+            pool,
+            alloc,
+            timeout,
+            provided_buffers,
+            lock,
+            follow,
+        } = config;
+        if provided_buffers || follow.is_some() {
+            anyhow::bail!("ReverseReader supports neither provided buffers nor follow");
+        }
+        if buffer_size == 0 || buffer_size % get_page_size() != 0 {
+            anyhow::bail!(
+                "buffer_size {} is not a multiple of the page size",
+                buffer_size
+            );
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(fpath)
+            .with_context(|| format!("open {} failed", fpath))?;
+        lock::lock(&file, fpath, false, lock)?;
+        let file_size = file
+            .metadata()
+            .with_context(|| format!("stat {} failed", fpath))?
+            .len();
+        let end_pos = end_pos.unwrap_or(file_size);
+        if end_pos > file_size {
+            anyhow::bail!("end_pos {} is larger than file size {}", end_pos, file_size);
+        }
+        let start_pos = start_pos.min(end_pos);
+
+        let mut buffers = stream_buffers(buffer_size, num_buffer, pool.as_ref(), alloc)?;
+        // a request linked to its timeout takes two entries
+        let entries = if timeout.is_some() {
+            2 * num_buffer
+        } else {
+            num_buffer
+        };
+        let mut ring = match ctx {
+            Some(ctx) => ctx.open_stream(Some(file.as_raw_fd()), &mut buffers)?,
+            None => StreamRing::private(entries as u32, file.as_raw_fd(), &mut buffers)?,
+        };
+        ring.set_timeout(timeout);
+
+        let bs = buffer_size as u64;
+        let num_blocks = if start_pos == end_pos {
+            0
+        } else {
+            end_pos.div_ceil(bs) - start_pos / bs
+        };
+        let mut reader = Self {
+            file,
+            fpath: fpath.to_string(),
+            buffer_size,
+            ring,
+            buffers,
+            start_pos,
+            end_pos,
+            num_blocks,
+            next_block: 0,
+            next_submit: 0,
+            handed_out: false,
+        };
+        while reader.next_submit < reader.num_blocks.min(num_buffer as u64) {
+            reader.submit_next()?;
+        }
+        Ok(reader)
+    }
+
+    /// the next block of data toward the start, the bytes in it are in file order.
+    /// `None` once `start_pos` is reached
+    pub fn next_chunk(&mut self) -> anyhow::Result<Option<&[u8]>> {
+        if self.handed_out {
+            self.handed_out = false;
+            if self.next_submit < self.num_blocks {
+                self.submit_next()?;
+            }
+        }
+        if self.next_block >= self.num_blocks {
+            return Ok(None);
+        }
+        let block = self.next_block;
+        let buf_idx = self.buf_idx(block);
+        let (_, begin, end) = self.block_range(block);
+        while self.buffers[buf_idx].len < end {
+            let cqe = self.ring.wait()?;
+            let idx = cqe.user_data as usize;
+            let at = self.block_range(self.block_of(idx)).0 + self.buffers[idx].len as u64;
+            let n = cqe
+                .into_result()
+                .with_context(|| format!("read {} at {} failed", self.fpath, at))?;
+            if n == 0 {
+                anyhow::bail!("unexpected eof reading {} at {}", self.fpath, at);
+            }
+            self.buffers[idx].len += n as usize;
+            let (idx_pos, _, need) = self.block_range(self.block_of(idx));
+            if self.buffers[idx].len < need {
+                // short read. O_DIRECT needs an aligned offset, so the unaligned part is read again
+                self.buffers[idx].len -= self.buffers[idx].len % get_page_size();
+                self.push_read(idx, idx_pos)?;
+            }
+        }
+        self.next_block += 1;
+        self.handed_out = true;
+        Ok(Some(&self.buffers[buf_idx][begin..end]))
+    }
+
+    /// the lines from the last to the first, without their `\n` or `\r\n`. a `\n` at the very end
+    /// does not start an empty line
+    pub fn lines(self) -> ReverseLines {
+        ReverseLines {
+            reader: self,
+            chunk: vec![],
+            chunk_end: 0,
+            tail: vec![],
+            started: false,
+            done: false,
+        }
+    }
+
+    fn buf_idx(&self, block: u64) -> usize {
+        (block % self.buffers.len() as u64) as usize
+    }
+
+    /// the block a buffer holds or is reading. the blocks of a buffer are `buffers.len()` apart
+    /// and it holds the last one submitted
+    fn block_of(&self, buf_idx: usize) -> u64 {
+        let n = self.buffers.len() as u64;
+        let last = self.next_submit - 1;
+        last - (last + n - buf_idx as u64) % n
+    }
+
+    /// the aligned file position of `block` and the range of its data in the buffer
+    fn block_range(&self, block: u64) -> (u64, usize, usize) {
+        let bs = self.buffer_size as u64;
+        let pos = ((self.end_pos - 1) / bs - block) * bs;
+        let begin = self.start_pos.saturating_sub(pos) as usize;
+        let end = (self.end_pos - pos).min(bs) as usize;
+        (pos, begin, end)
+    }
+
+    fn submit_next(&mut self) -> anyhow::Result<()> {
+        let block = self.next_submit;
+        let buf_idx = self.buf_idx(block);
+        self.next_submit += 1;
+        self.buffers[buf_idx].len = 0;
+        let (pos, _, _) = self.block_range(block);
+        self.push_read(buf_idx, pos)
+    }
+
+    /// read the part of the block that is not filled yet, up to the page after its data
+    fn push_read(&mut self, buf_idx: usize, pos: u64) -> anyhow::Result<()> {
+        let (_, _, end) = self.block_range(self.block_of(buf_idx));
+        let page_size = get_page_size();
+        let buf = &mut self.buffers[buf_idx];
+        let filled = buf.len;
+        let len = end.div_ceil(page_size) * page_size - filled;
+        let sqe = io_uring::opcode::ReadFixed::new(
+            self.ring.file(),
+            unsafe { buf.as_mut_ptr().add(filled) },
+            len as u32,
+            self.ring.buf_index(buf_idx),
+        )
+        .offset(pos + filled as u64)
+        .build()
+        .user_data(buf_idx as u64);
+
+        unsafe { self.ring.push(sqe) }
+    }
+}
+
+/// see `ReverseReader::lines`
+pub struct ReverseLines {
+    reader: ReverseReader,
+    chunk: Vec<u8>, // the block read last, `chunk[..chunk_end]` is not split yet
+    chunk_end: usize,
+    tail: Vec<Vec<u8>>, // the end of the line being collected, one piece per block, last block first
+    started: bool,
+    done: bool,
+}
+
+impl ReverseLines {
+    fn next_line(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        loop {
+            let rest = &self.chunk[..self.chunk_end];
+            if let Some(i) = rest.iter().rposition(|&b| b == b'\n') {
+                let line = join(&rest[i + 1..], &mut self.tail);
+                self.chunk_end = i;
+                return Ok(Some(line));
+            }
+            if !rest.is_empty() {
+                self.tail.push(rest.to_vec());
+                self.chunk_end = 0;
+            }
+            let Some(chunk) = self.reader.next_chunk()? else {
+                if self.done {
+                    return Ok(None);
+                }
+                // the first line
+                self.done = true;
+                if self.started {
+                    return Ok(Some(join(&[], &mut self.tail)));
+                }
+                return Ok(None);
+            };
+            self.chunk.clear();
+            self.chunk.extend_from_slice(chunk);
+            if !self.started {
+                self.started = true;
+                if self.chunk.last() == Some(&b'\n') {
+                    self.chunk.pop();
+                }
+            }
+            self.chunk_end = self.chunk.len();
+        }
+    }
+}
+
+/// `head` followed by the pieces of `tail`, last first, without a `\r` at the end
+fn join(head: &[u8], tail: &mut Vec<Vec<u8>>) -> Vec<u8> {
+    let len = head.len() + tail.iter().map(|piece| piece.len()).sum::<usize>();
+    let mut line = Vec::with_capacity(len);
+    line.extend_from_slice(head);
+    for piece in tail.drain(..).rev() {
+        line.extend_from_slice(&piece);
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    line
+}
+
+impl Iterator for ReverseLines {
+    type Item = anyhow::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_line().transpose()
+    }
+}
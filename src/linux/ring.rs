@@ -0,0 +1,341 @@
+#![cfg(target_os = "linux")]
+use std::{
+    collections::VecDeque,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use io_uring::{IoUring, Submitter, opcode, squeue, types};
+
+#[cfg(any(test, feature = "fault-injection"))]
+use super::fault::{FaultInjector, FaultPlan};
+
+/// a completion queue entry copied out of the ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub user_data: u64,
+    pub result: i32,
+    pub flags: u32,
+}
+
+impl Completion {
+    /// negative results are `-errno`
+    pub fn into_result(self) -> std::io::Result<u32> {
+        if self.result < 0 {
+            Err(std::io::Error::from_raw_os_error(-self.result))
+        } else {
+            Ok(self.result as u32)
+        }
+    }
+}
+
+/// thin wrapper around `IoUring` that keeps track of the requests in flight
+/// and buffers reaped completions. all readers and writers talk to the kernel through it,
+/// which also makes it the place where faults are injected in tests.
+pub struct Ring {
+    ring: IoUring,
+    in_flight: usize,
+    ready: VecDeque<Completion>,
+    eventfd: Option<OwnedFd>,
+    #[cfg(any(test, feature = "fault-injection"))]
+    faults: Option<FaultInjector>,
+}
+
+impl Ring {
+    pub fn new(entries: u32) -> anyhow::Result<Self> {
+        let ring = IoUring::new(entries).context("create io_uring failed")?;
+        Ok(Self {
+            ring,
+            in_flight: 0,
+            ready: VecDeque::new(),
+            eventfd: None,
+            #[cfg(any(test, feature = "fault-injection"))]
+            faults: None,
+        })
+    }
+
+    pub fn submitter(&self) -> Submitter<'_> {
+        self.ring.submitter()
+    }
+
+    /// number of requests pushed whose completion has not been handed out yet
+    pub fn in_flight(&self) -> usize {
+        self.in_flight + self.ready.len()
+    }
+
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn set_fault_plan(&mut self, plan: FaultPlan) {
+        self.faults = Some(FaultInjector::new(plan));
+    }
+
+    /// a non-blocking eventfd registered with the ring, readable once a completion is posted.
+    /// created on first use, `try_next` resets it.
+    pub fn eventfd(&mut self) -> anyhow::Result<RawFd> {
+        if let Some(fd) = self.eventfd.as_ref() {
+            return Ok(fd.as_raw_fd());
+        }
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("create eventfd failed");
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        self.ring
+            .submitter()
+            .register_eventfd(fd.as_raw_fd())
+            .context("register eventfd failed")?;
+        Ok(self.eventfd.insert(fd).as_raw_fd())
+    }
+
+    /// # Safety
+    /// the buffers referenced by `sqe` must stay valid until its completion is returned by `wait`
+    pub unsafe fn push(&mut self, sqe: &squeue::Entry) -> anyhow::Result<()> {
+        if self.ring.submission().is_full() {
+            self.ring.submit().context("io_uring submit failed")?;
+        }
+        unsafe {
+            self.ring
+                .submission()
+                .push(sqe)
+                .context("Failed to push submission queue entry")?;
+        }
+        #[cfg(any(test, feature = "fault-injection"))]
+        if let Some(faults) = self.faults.as_mut() {
+            faults.on_submit(sqe.get_user_data());
+        }
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// push `sqes` as one chain, each starts once the one before it succeeded. with a `timeout`
+    /// every entry is linked to a timeout that cancels it after that long, the timeouts complete
+    /// with `timeout_user_data`. all are submitted right away, the kernel copies the timespec then.
+    ///
+    /// # Safety
+    /// same as `push`
+    pub unsafe fn push_link(
+        &mut self,
+        sqes: &[squeue::Entry],
+        timeout: Option<(Duration, u64)>,
+    ) -> anyhow::Result<()> {
+        let ts = timeout.map(|(timeout, _)| types::Timespec::from(timeout));
+        let mut entries = vec![];
+        for (i, sqe) in sqes.iter().enumerate() {
+            let last = i + 1 == sqes.len();
+            match (ts.as_ref(), timeout) {
+                (Some(ts), Some((_, user_data))) => {
+                    entries.push(sqe.clone().flags(squeue::Flags::IO_LINK));
+                    let timeout = opcode::LinkTimeout::new(ts).build().user_data(user_data);
+                    entries.push(if last {
+                        timeout
+                    } else {
+                        timeout.flags(squeue::Flags::IO_LINK)
+                    });
+                }
+                _ if last => entries.push(sqe.clone()),
+                _ => entries.push(sqe.clone().flags(squeue::Flags::IO_LINK)),
+            }
+        }
+        // a link must not be split across two submissions
+        let free = {
+            let sq = self.ring.submission();
+            sq.capacity() - sq.len()
+        };
+        if free < entries.len() {
+            self.ring.submit().context("io_uring submit failed")?;
+        }
+        unsafe {
+            self.ring
+                .submission()
+                .push_multiple(&entries)
+                .context("Failed to push submission queue entry")?;
+        }
+        #[cfg(any(test, feature = "fault-injection"))]
+        if let Some(faults) = self.faults.as_mut() {
+            for sqe in sqes {
+                faults.on_submit(sqe.get_user_data());
+            }
+        }
+        self.in_flight += entries.len();
+        self.submit()
+    }
+
+    /// submit the pending entries without waiting for any completion
+    pub fn submit(&mut self) -> anyhow::Result<()> {
+        self.ring.submit().context("io_uring submit failed")?;
+        Ok(())
+    }
+
+    /// block until a completion is available and return it
+    pub fn wait(&mut self) -> anyhow::Result<Completion> {
+        loop {
+            if let Some(cqe) = self.ready.pop_front() {
+                return Ok(cqe);
+            }
+            if self.in_flight == 0 {
+                anyhow::bail!("wait on io_uring without any request in flight");
+            }
+            self.reap(self.min_wait())?;
+        }
+    }
+
+    /// like `wait`, but gives up with `None` once `deadline` passes
+    pub fn wait_until(&mut self, deadline: Instant) -> anyhow::Result<Option<Completion>> {
+        loop {
+            if let Some(cqe) = self.ready.pop_front() {
+                return Ok(Some(cqe));
+            }
+            if self.in_flight == 0 {
+                anyhow::bail!("wait on io_uring without any request in flight");
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            let ts = types::Timespec::from(deadline - now);
+            self.reap_timeout(self.min_wait(), Some(&ts))?;
+        }
+    }
+
+    /// return a completion if one is available, without blocking
+    pub fn try_next(&mut self) -> anyhow::Result<Option<Completion>> {
+        if let Some(fd) = self.eventfd.as_ref() {
+            // reset before reaping, a completion posted after the reap signals it again
+            let mut count = 0_u64;
+            unsafe { libc::read(fd.as_raw_fd(), &mut count as *mut u64 as *mut _, 8) };
+        }
+        if self.ready.is_empty() && self.in_flight > 0 {
+            self.reap(0)?;
+        }
+        Ok(self.ready.pop_front())
+    }
+
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub(crate) fn min_wait(&self) -> usize {
+        match self.faults.as_ref() {
+            Some(faults) if faults.reorder() => self.in_flight,
+            _ => 1,
+        }
+    }
+
+    #[cfg(not(any(test, feature = "fault-injection")))]
+    pub(crate) fn min_wait(&self) -> usize {
+        1
+    }
+
+    /// submit and wait for at least `want` completions, then move everything available into `ready`
+    fn reap(&mut self, want: usize) -> anyhow::Result<()> {
+        self.reap_timeout(want, None)
+    }
+
+    /// `reap`, waiting no longer than `timeout`
+    fn reap_timeout(
+        &mut self,
+        want: usize,
+        timeout: Option<&types::Timespec>,
+    ) -> anyhow::Result<()> {
+        loop {
+            let submitted = match timeout {
+                Some(ts) => self
+                    .ring
+                    .submitter()
+                    .submit_with_args(want, &types::SubmitArgs::new().timespec(ts)),
+                None => self.ring.submit_and_wait(want),
+            };
+            match submitted {
+                Ok(_) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) if e.raw_os_error() == Some(libc::ETIME) => break,
+                Err(e) => return Err(e).context("io_uring submit_and_wait failed"),
+            }
+        }
+
+        self.collect();
+        Ok(())
+    }
+
+    /// move the completions posted so far into `ready`, without entering the kernel
+    pub(crate) fn collect(&mut self) {
+        let start = self.ready.len();
+        for cqe in self.ring.completion() {
+            self.in_flight -= 1;
+            self.ready.push_back(Completion {
+                user_data: cqe.user_data(),
+                result: cqe.result(),
+                flags: cqe.flags(),
+            });
+        }
+
+        #[cfg(any(test, feature = "fault-injection"))]
+        if let Some(faults) = self.faults.as_mut() {
+            let reaped = self.ready.split_off(start);
+            faults.on_complete(reaped, &mut self.ready);
+        }
+        #[cfg(not(any(test, feature = "fault-injection")))]
+        let _ = start;
+    }
+
+    /// the next completion moved into `ready`
+    pub(crate) fn next_ready(&mut self) -> Option<Completion> {
+        self.ready.pop_front()
+    }
+
+    /// requests submitted whose completion has not been collected yet
+    pub(crate) fn pending(&self) -> usize {
+        self.in_flight
+    }
+
+    /// the ring's fd, for `enter_wait`
+    pub(crate) fn fd(&self) -> RawFd {
+        self.ring.as_raw_fd()
+    }
+}
+
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_ENTER_EXT_ARG: u32 = 8;
+
+/// `struct io_uring_getevents_arg`
+#[repr(C)]
+struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    min_wait_usec: u32,
+    ts: u64,
+}
+
+/// block until `want` completions are posted on the ring `fd` or `timeout` passes, without
+/// submitting anything. the `Ring` may be used by other threads meanwhile, its `collect`
+/// picks the completions up afterwards. an interrupted wait returns early.
+pub(crate) fn enter_wait(fd: RawFd, want: usize, timeout: Option<Duration>) -> anyhow::Result<()> {
+    let ts = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs() as _,
+        tv_nsec: timeout.subsec_nanos() as _,
+    });
+    let arg = GeteventsArg {
+        sigmask: 0,
+        sigmask_sz: 0,
+        min_wait_usec: 0,
+        ts: ts
+            .as_ref()
+            .map_or(0, |ts| ts as *const libc::timespec as u64),
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_io_uring_enter,
+            fd,
+            0u32,
+            want as u32,
+            IORING_ENTER_GETEVENTS | IORING_ENTER_EXT_ARG,
+            &arg as *const GeteventsArg,
+            std::mem::size_of::<GeteventsArg>(),
+        )
+    };
+    if ret < 0 {
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) | Some(libc::ETIME) => {}
+            _ => return Err(e).context("io_uring_enter failed"),
+        }
+    }
+    Ok(())
+}
@@ -1,10 +1,13 @@
 #![cfg(target_os = "linux")]
 use std::{
+    collections::BTreeMap,
     fs::{self, OpenOptions},
+    io::ErrorKind,
     os::{
-        fd::AsRawFd,
-        unix::fs::{FileExt, OpenOptionsExt},
+        fd::{AsRawFd, RawFd},
+        unix::fs::{FileExt, MetadataExt, OpenOptionsExt},
     },
+    time::{Duration, Instant},
 };
 
 use crate::{
@@ -12,24 +15,120 @@ use crate::{
     linux::utils::get_page_size,
 };
 use anyhow::Context;
-use io_uring::IoUring;
 
-use super::buffer::Buffer;
+#[cfg(any(test, feature = "fault-injection"))]
+use super::fault::FaultPlan;
+use super::{
+    buf_ring::ProvidedBufRing,
+    buffer::{AllocOptions, Buffer},
+    buffer_pool::{BufferPool, stream_buffers},
+    io_context::{IoContext, StreamRing},
+    lock::{self, LockMode},
+    ring::Completion,
+};
+
+/// buffer settings of a `SequentialReader`
+#[derive(Clone)]
+pub struct ReaderConfig {
+    pub buffer_size: usize,
+    pub num_buffer: usize,
+    /// borrow the buffers from this pool instead of allocating them, `buffer_size` must match the pool's
+    pub pool: Option<BufferPool>,
+    /// huge pages and mlock of the buffers, the pool's options apply to pooled buffers
+    pub alloc: AllocOptions,
+    /// cancel a read still running after this long, it then fails with `ErrorKind::TimedOut`
+    pub timeout: Option<Duration>,
+    /// let the kernel pick a free buffer for every read from a provided buffer ring
+    /// (`IORING_REGISTER_PBUF_RING`, linux 5.19+) instead of binding each read to a buffer
+    pub provided_buffers: bool,
+    /// take a shared `flock` on the file
+    pub lock: LockMode,
+    /// keep reading what is appended to the file instead of stopping at its end, checking the size
+    /// with a backoff of at most this long. a read returns what there is once it has some data.
+    /// `end_pos` must be `None`, a truncated or replaced file fails the read with `FileChanged`
+    pub follow: Option<Duration>,
+}
+
+/// how a followed file changed under a `SequentialReader`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileChange {
+    /// shorter than what was read already
+    Truncated,
+    /// the path is removed or names another file, e.g. after log rotation
+    Replaced,
+}
+
+/// the file a `SequentialReader` follows changed, a new reader is needed
+#[derive(Debug)]
+pub struct FileChanged {
+    pub fpath: String,
+    pub change: FileChange,
+}
+
+impl std::fmt::Display for FileChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.change {
+            FileChange::Truncated => write!(f, "{} was truncated", self.fpath),
+            FileChange::Replaced => write!(f, "{} was replaced", self.fpath),
+        }
+    }
+}
+
+impl std::error::Error for FileChanged {}
+
+impl ReaderConfig {
+    pub fn new(buffer_size: usize, num_buffer: usize) -> Self {
+        Self {
+            buffer_size,
+            num_buffer,
+            pool: None,
+            alloc: AllocOptions::default(),
+            timeout: None,
+            provided_buffers: false,
+            lock: LockMode::None,
+            follow: None,
+        }
+    }
+}
+
 pub struct SequentialReader {
     #[allow(unused)]
     file: fs::File, // 不能删掉。要保证文件是打开的！
     fpath: String,
     buff_size: usize,
-    ring: IoUring,
+    ring: StreamRing,
+    provided: Option<ProvidedState>, // must be dropped after `ring`
     buffers: Vec<Buffer>,
     buffers_flag: Vec<BufferStatus>,
+    buffers_pos: Vec<u64>,        // 每个 buffer 对应的文件位置
     data_location: BufferDataPos, // 即将要读取的 buffer 以及 offset
-    pending_io: usize,
     init_flag: bool,
     file_pos_cursor: u64,
     end_pos: u64,
+    deadline: Option<Instant>, // of the current `read2buf_timeout` call
+    nonblocking: bool,         // inside `try_read`
+    follow: Option<Duration>,
+    file_id: (u64, u64), // dev and inode, to notice a replaced file in follow mode
+    line: Vec<u8>,       // a line of `read_until` across buffers
+    tail_file: Option<fs::File>, // the file without O_DIRECT, for the unaligned last read
+}
+
+/// what the reader finds for the data to hand out next
+enum NextBuf {
+    Ready(usize),
+    Eof,
+    Pending, // only in `try_read`
+}
+
+/// state of the provided buffer ring mode
+struct ProvidedState {
+    buf_ring: ProvidedBufRing,
+    bgid: u16,
+    chunk_start: u64,            // file position of chunk 0
+    next_chunk: u64,             // the chunk to hand out next
+    next_submit: u64,            // the chunk to read next
+    ready: BTreeMap<u64, usize>, // chunk -> buffer holding it until released, completed in any order
 }
-unsafe impl Send for SequentialReader {}
 
 impl SequentialReader {
     /// the caller need to make sure the sequential meta is valid
@@ -40,11 +139,76 @@ impl SequentialReader {
         num_buffer: usize,
         end_pos: Option<u64>,
     ) -> anyhow::Result<Self> {
+        Self::with_config(
+            fpath,
+            start_pos,
+            end_pos,
+            ReaderConfig::new(buffer_size, num_buffer),
+        )
+    }
+
+    pub fn with_config(
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        config: ReaderConfig,
+    ) -> anyhow::Result<Self> {
+        Self::new_in(None, fpath, start_pos, end_pos, config)
+    }
+
+    /// `ctx`: the shared ring to submit to, a private ring is created if `None`
+    pub(crate) fn new_in(
+        ctx: Option<&IoContext>,
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        config: ReaderConfig,
+    ) -> anyhow::Result<Self> {
+        Self::open(ctx, fpath, start_pos, end_pos, config, None)
+    }
+
+    /// read into `buffers`, the caller took them from `config.pool`
+    pub(crate) fn with_buffers(
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        config: ReaderConfig,
+        buffers: Vec<Buffer>,
+    ) -> anyhow::Result<Self> {
+        Self::open(None, fpath, start_pos, end_pos, config, Some(buffers))
+    }
+
+    /// `buffers` are used instead of acquiring or allocating them
+    fn open(
+        ctx: Option<&IoContext>,
+        fpath: &str,
+        start_pos: u64,
+        end_pos: Option<u64>,
+        config: ReaderConfig,
+        buffers: Option<Vec<Buffer>>,
+    ) -> anyhow::Result<Self> {
+        let ReaderConfig {
+            buffer_size,
+            num_buffer,
+            pool,
+            alloc,
+            timeout,
+            provided_buffers,
+            lock,
+            follow,
+        } = config;
+        if follow.is_some() && end_pos.is_some() {
+            anyhow::bail!("a reader following {} can not have an end_pos", fpath);
+        }
         let file = OpenOptions::new()
             .read(true)
             .custom_flags(libc::O_DIRECT)
             .open(fpath)
-            .unwrap();
+            .with_context(|| format!("open {} failed", fpath))?;
+        lock::lock(&file, fpath, false, lock)?;
+        let meta = file
+            .metadata()
+            .with_context(|| format!("stat {} failed", fpath))?;
 
         let page_size = get_page_size();
         assert_eq!(buffer_size % page_size, 0);
@@ -53,41 +217,50 @@ impl SequentialReader {
             anyhow::bail!("get_page_size returned 0, which is invalid");
         }
 
-        let ring = IoUring::new(num_buffer as u32).unwrap();
-
-        let mut buffers: Vec<Buffer> = (0..num_buffer)
-            .map(|_| Buffer::new(buffer_size, page_size))
-            .collect();
-
-        let iovecs = buffers
-            .iter_mut()
-            .map(|buf| {
-                let buf_len = buf.cap();
-                libc::iovec {
-                    iov_base: buf.as_mut_ptr() as *mut _,
-                    iov_len: buf_len as usize,
-                }
-            })
-            .collect::<Vec<_>>();
+        let mut buffers = match buffers {
+            Some(buffers) => buffers,
+            None => stream_buffers(buffer_size, num_buffer, pool.as_ref(), alloc)?,
+        };
 
         let offset = start_pos as usize % buffer_size;
         let readstart = start_pos - offset as u64;
 
         let data_location = BufferDataPos {
             buf_idx: 0,
-            offset: offset as usize,
+            offset,
         };
 
         let buffers_flag = vec![BufferStatus::Ready4Submit; num_buffer];
 
-        unsafe {
-            ring.submitter()
-                .register_buffers(iovecs.as_slice())
-                .expect("register buffers error");
-            ring.submitter()
-                .register_files(&[file.as_raw_fd()])
-                .expect("register file error");
-        }
+        // a request linked to its timeout takes two entries
+        let entries = if timeout.is_some() {
+            2 * num_buffer
+        } else {
+            num_buffer
+        };
+        let mut ring = match ctx {
+            Some(ctx) => ctx.open_stream(Some(file.as_raw_fd()), &mut buffers)?,
+            None => StreamRing::private(entries as u32, file.as_raw_fd(), &mut buffers)?,
+        };
+        ring.set_timeout(timeout);
+
+        let provided = if provided_buffers {
+            let mut buf_ring = ProvidedBufRing::new(num_buffer)?;
+            let bgid = ring.register_buf_ring(&buf_ring)?;
+            for (bid, buf) in buffers.iter_mut().enumerate() {
+                buf_ring.push(buf.as_mut_ptr(), buf.cap() as u32, bid as u16);
+            }
+            Some(ProvidedState {
+                buf_ring,
+                bgid,
+                chunk_start: readstart,
+                next_chunk: 0,
+                next_submit: 0,
+                ready: BTreeMap::new(),
+            })
+        } else {
+            None
+        };
 
         let file_size = crate::utils::get_file_size(fpath);
         let end_pos = match end_pos {
@@ -104,38 +277,206 @@ impl SequentialReader {
             fpath: fpath.to_string(),
             buff_size: buffer_size,
             ring,
+            provided,
             buffers,
             buffers_flag,
-            data_location: data_location,
-            pending_io: 0,
+            buffers_pos: vec![0; num_buffer],
+            data_location,
             init_flag: false,
             file_pos_cursor: readstart,
-            end_pos: end_pos,
+            end_pos,
+            deadline: None,
+            nonblocking: false,
+            follow,
+            file_id: (meta.dev(), meta.ino()),
+            line: vec![],
+            tail_file: None,
         })
     }
 
+    /// script faults for the requests this reader submits from now on
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn set_fault_plan(&mut self, plan: FaultPlan) {
+        self.ring.set_fault_plan(plan);
+    }
+
     pub fn read2buf(&mut self, buf: &mut [u8]) -> anyhow::Result<usize> {
         self.read_exact(buf)
     }
 
+    /// `read2buf` that fails with `ErrorKind::TimedOut` if the data does not arrive within `timeout`.
+    /// the reads in flight are cancelled then, the reader can not be used after a timeout.
+    pub fn read2buf_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> anyhow::Result<usize> {
+        self.deadline = Some(Instant::now() + timeout);
+        let res = self.read_exact(buf);
+        self.deadline = None;
+        res
+    }
+
+    /// copy only the data that is already read ahead, never waits for the disk.
+    /// returns 0 at the end, fails with `ErrorKind::WouldBlock` if no data is ready yet,
+    /// `eventfd` tells when to try again.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        self.nonblocking = true;
+        let res = self.read_exact(buf);
+        self.nonblocking = false;
+        res
+    }
+
+    /// the data already read ahead, without copying it. empty at the end,
+    /// fails with `ErrorKind::WouldBlock` like `try_read`. `consume` marks it as read.
+    pub fn try_fill_buf(&mut self) -> anyhow::Result<&[u8]> {
+        self.nonblocking = true;
+        let res = self.fill_buf_inner();
+        self.nonblocking = false;
+        let buf_idx = match res? {
+            NextBuf::Ready(buf_idx) => buf_idx,
+            NextBuf::Eof => return Ok(&[]),
+            NextBuf::Pending => return Err(std::io::Error::from(ErrorKind::WouldBlock).into()),
+        };
+        let buf = &self.buffers[buf_idx];
+        Ok(&buf[self.data_location.offset..buf.len()])
+    }
+
+    /// the data read ahead, waiting for the next buffer if none is ready. empty at the end.
+    /// `consume` marks it as read.
+    pub fn fill_buf(&mut self) -> anyhow::Result<&[u8]> {
+        let buf_idx = match self.fill_buf_inner()? {
+            NextBuf::Ready(buf_idx) => buf_idx,
+            NextBuf::Eof => return Ok(&[]),
+            NextBuf::Pending => return Err(std::io::Error::from(ErrorKind::WouldBlock).into()),
+        };
+        let buf = &self.buffers[buf_idx];
+        Ok(&buf[self.data_location.offset..buf.len()])
+    }
+
+    /// the data up to and including the next `delim`, or up to the end. `None` at the end.
+    /// the slice points into the buffer when the data lies in one, it is copied when it crosses
+    /// into the next buffer. data longer than `max_len` before `delim` fails with
+    /// `ErrorKind::InvalidData`, the reader is left within it.
+    pub fn read_until(
+        &mut self,
+        delim: u8,
+        max_len: Option<usize>,
+    ) -> anyhow::Result<Option<&[u8]>> {
+        let max_len = max_len.unwrap_or(usize::MAX);
+        let too_long = || {
+            anyhow::Error::new(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("record longer than {} bytes", max_len),
+            ))
+        };
+
+        let data = self.fill_buf()?;
+        let avail = data.len();
+        if avail == 0 {
+            return Ok(None);
+        }
+        let found = data.iter().position(|&b| b == delim);
+        if let Some(i) = found
+            && i + 1 < avail
+        {
+            // `consume` keeps the buffer as the rest of it is not read yet
+            if i > max_len {
+                return Err(too_long());
+            }
+            let start = self.data_location.offset;
+            self.consume(i + 1)?;
+            let buf = &self.buffers[self.data_location.buf_idx];
+            return Ok(Some(&buf[start..start + i + 1]));
+        }
+
+        let mut line = std::mem::take(&mut self.line);
+        line.clear();
+        loop {
+            let data = self.fill_buf()?;
+            if data.is_empty() {
+                break;
+            }
+            let found = data.iter().position(|&b| b == delim);
+            let take = found.map_or(data.len(), |i| i + 1);
+            let content = line.len() + found.unwrap_or(take);
+            if content > max_len {
+                self.line = line;
+                return Err(too_long());
+            }
+            line.extend_from_slice(&data[..take]);
+            self.consume(take)?;
+            if found.is_some() {
+                break;
+            }
+        }
+        self.line = line;
+        Ok(Some(&self.line))
+    }
+
+    /// the lines without their `\n` or `\r\n`, see `Lines::next_line`
+    pub fn lines(&mut self) -> Lines<'_> {
+        Lines {
+            reader: self,
+            max_len: None,
+        }
+    }
+
+    /// `amt` bytes of the slice returned by `try_fill_buf` are used
+    pub fn consume(&mut self, amt: usize) -> anyhow::Result<()> {
+        let buf_idx = self.data_location.buf_idx;
+        self.data_location.offset += amt;
+        assert!(self.data_location.offset <= self.buffers[buf_idx].len());
+        if self.data_location.offset == self.buffers[buf_idx].len() {
+            self.data_location.offset = 0;
+            self.release_buf(buf_idx)?;
+        }
+        Ok(())
+    }
+
+    /// the next buffer with data not handed out yet
+    fn fill_buf_inner(&mut self) -> anyhow::Result<NextBuf> {
+        loop {
+            let next = self.next_ready_buf()?;
+            if let NextBuf::Ready(buf_idx) = next
+                && self.data_location.offset == self.buffers[buf_idx].len()
+            {
+                // the start position may be at the end of the first buffer
+                self.data_location.offset = 0;
+                self.release_buf(buf_idx)?;
+                continue;
+            }
+            return Ok(next);
+        }
+    }
+
+    /// an eventfd that becomes readable when a read completes, `try_read` resets it.
+    /// with a shared `IoContext` it is the context's eventfd.
+    pub fn eventfd(&mut self) -> anyhow::Result<RawFd> {
+        self.ring.eventfd()
+    }
+
     fn read_exact(&mut self, data: &mut [u8]) -> anyhow::Result<usize> {
         let record_len = data.len();
         let mut data_start = 0;
 
         while data_start < record_len {
-            let buf_idx = self.data_location.buf_idx;
-            self.wait_buf_ready4read(buf_idx)?;
-            if self.buffers_flag[buf_idx] == BufferStatus::Invalid {
+            let next = if data_start > 0 && self.follow.is_some() {
+                // hand out what there is instead of waiting for the file to grow
+                self.next_ready_buf_once()?
+            } else {
+                self.next_ready_buf()?
+            };
+            let buf_idx = match next {
+                NextBuf::Ready(buf_idx) => buf_idx,
                 // no more data to read
-                return Ok(data_start);
-            }
+                NextBuf::Eof => return Ok(data_start),
+                NextBuf::Pending if data_start > 0 => return Ok(data_start),
+                NextBuf::Pending => return Err(std::io::Error::from(ErrorKind::WouldBlock).into()),
+            };
 
             let expected_data_size = record_len - data_start;
 
             let (fill_size, current_buf_remaining) = {
                 let buf = &self.buffers[buf_idx];
 
-                let current_buf_remaining = (buf.len() - self.data_location.offset) as usize;
+                let current_buf_remaining = buf.len() - self.data_location.offset;
                 let fill_size = current_buf_remaining.min(expected_data_size);
                 data[data_start..data_start + fill_size].copy_from_slice(
                     &buf[self.data_location.offset..self.data_location.offset + fill_size],
@@ -147,19 +488,153 @@ impl SequentialReader {
             data_start += fill_size;
             if expected_data_size >= current_buf_remaining {
                 // current buffer is not enough, need to read next buffer
-                // self.ready4sqe_buffer_indices.push(buf_idx);
-                let next_buf_idx: usize = (buf_idx + 1) % self.buffers.len();
-                self.data_location.buf_idx = next_buf_idx;
                 self.data_location.offset = 0;
-
-                self.buffers_flag[buf_idx] = BufferStatus::Ready4Submit;
-                self.submit_read_event(buf_idx)?;
+                self.release_buf(buf_idx)?;
             }
         }
 
         Ok(record_len)
     }
 
+    /// the buffer holding the data to hand out next
+    fn next_ready_buf(&mut self) -> anyhow::Result<NextBuf> {
+        loop {
+            let next = self.next_ready_buf_once()?;
+            if matches!(next, NextBuf::Eof) && self.follow.is_some() {
+                if self.wait_for_growth()? {
+                    continue;
+                }
+                return Ok(NextBuf::Pending);
+            }
+            return Ok(next);
+        }
+    }
+
+    fn next_ready_buf_once(&mut self) -> anyhow::Result<NextBuf> {
+        if self.provided.is_some() {
+            return self.wait_chunk_ready();
+        }
+        let buf_idx = self.data_location.buf_idx;
+        self.wait_buf_ready4read(buf_idx)?;
+        Ok(match self.buffers_flag[buf_idx] {
+            BufferStatus::Ready4Process => NextBuf::Ready(buf_idx),
+            BufferStatus::Invalid => NextBuf::Eof,
+            _ => NextBuf::Pending,
+        })
+    }
+
+    /// in follow mode at the end of the data, wait until the file grows and restart the reads at the
+    /// old end. `false` if it has not grown yet in `try_read`
+    fn wait_for_growth(&mut self) -> anyhow::Result<bool> {
+        let max_backoff = self.follow.unwrap_or_default();
+        let mut backoff = Duration::from_millis(1).min(max_backoff);
+        loop {
+            let size = self
+                .file
+                .metadata()
+                .with_context(|| format!("stat {} failed", self.fpath))?
+                .len();
+            let change = if size < self.end_pos {
+                Some(FileChange::Truncated)
+            } else if size > self.end_pos {
+                self.restart_at_end(size);
+                return Ok(true);
+            } else {
+                match fs::metadata(&self.fpath) {
+                    Ok(meta) if (meta.dev(), meta.ino()) == self.file_id => None,
+                    Ok(_) => Some(FileChange::Replaced),
+                    Err(e) if e.kind() == ErrorKind::NotFound => Some(FileChange::Replaced),
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("stat {} failed", self.fpath));
+                    }
+                }
+            };
+            if let Some(change) = change {
+                return Err(FileChanged {
+                    fpath: self.fpath.clone(),
+                    change,
+                }
+                .into());
+            }
+
+            if self.nonblocking {
+                return Ok(false);
+            }
+            let mut sleep = backoff;
+            if let Some(deadline) = self.deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(std::io::Error::from(ErrorKind::TimedOut))
+                        .with_context(|| format!("{} did not grow in time", self.fpath));
+                }
+                sleep = sleep.min(left);
+            }
+            std::thread::sleep(sleep);
+            backoff = (backoff * 2).min(max_backoff);
+        }
+    }
+
+    /// everything up to `end_pos` is handed out and no read is in flight. read on from there up
+    /// to `new_end`, starting at the buffer aligned position before it for O_DIRECT
+    fn restart_at_end(&mut self, new_end: u64) {
+        let offset = (self.end_pos % self.buff_size as u64) as usize;
+        let readstart = self.end_pos - offset as u64;
+        self.data_location = BufferDataPos { buf_idx: 0, offset };
+        self.buffers_flag.fill(BufferStatus::Ready4Submit);
+        self.init_flag = false;
+        self.file_pos_cursor = readstart;
+        self.end_pos = new_end;
+        if let Some(provided) = self.provided.as_mut() {
+            provided.chunk_start = readstart;
+            provided.next_chunk = 0;
+            provided.next_submit = 0;
+            provided.ready.clear();
+        }
+    }
+
+    /// the next completion of the reader, `None` if there is none yet in `try_read`
+    fn next_completion(&mut self) -> anyhow::Result<Option<Completion>> {
+        if self.nonblocking {
+            return self.ring.try_wait();
+        }
+        self.ring.wait_deadline(self.deadline).map(Some)
+    }
+
+    /// the data of `buf_idx` is consumed, reuse the buffer for the next read
+    fn release_buf(&mut self, buf_idx: usize) -> anyhow::Result<()> {
+        if let Some(provided) = self.provided.as_mut() {
+            let buf = &mut self.buffers[buf_idx];
+            provided
+                .buf_ring
+                .push(buf.as_mut_ptr(), buf.cap() as u32, buf_idx as u16);
+            provided.ready.remove(&provided.next_chunk);
+            provided.next_chunk += 1;
+            return self.submit_provided_read();
+        }
+        let next_buf_idx: usize = (buf_idx + 1) % self.buffers.len();
+        self.data_location.buf_idx = next_buf_idx;
+
+        self.buffers_flag[buf_idx] = BufferStatus::Ready4Submit;
+        self.submit_read_event(buf_idx)
+    }
+
+    /// submit the first reads into all buffers, they are otherwise submitted by the first read.
+    /// returns right away
+    pub(crate) fn start_reads(&mut self) -> anyhow::Result<()> {
+        if self.init_flag {
+            return Ok(());
+        }
+        self.init_flag = true;
+        for idx in 0..self.buffers.len() {
+            if self.provided.is_some() {
+                self.submit_provided_read()?;
+            } else {
+                self.submit_read_event(idx)?;
+            }
+        }
+        self.ring.submit()
+    }
+
     fn wait_buf_ready4read(&mut self, buf_idx: usize) -> anyhow::Result<()> {
         if self.buffers_flag[buf_idx] == BufferStatus::Ready4Process {
             return Ok(());
@@ -171,26 +646,40 @@ impl SequentialReader {
 
         // initial state
         if !self.init_flag {
-            for idx in 0..self.buffers.len() {
-                self.submit_read_event(idx)?;
+            self.start_reads()?;
+            if self.buffers_flag[buf_idx] != BufferStatus::Ready4Submit {
+                // the whole rest of the file was read right away, or there is none
+                return Ok(());
             }
-            self.init_flag = true;
         }
-        while self.pending_io > 0 {
-            self.ring
-                .submit_and_wait(1)
-                .expect("Failed to submit and wait");
-            let cqe = self
-                .ring
-                .completion()
-                .next()
-                .expect("No completion event found");
-            self.pending_io -= 1;
-
-            let idx = cqe.user_data() as usize;
+        while self.ring.in_flight() > 0 {
+            let Some(cqe) = self.next_completion()? else {
+                return Ok(());
+            };
+            let idx = cqe.user_data as usize;
+            let n = cqe.into_result().with_context(|| {
+                format!(
+                    "read {} at {} failed",
+                    self.fpath,
+                    self.buffers_pos[idx] + self.buffers[idx].len as u64
+                )
+            })?;
+            if n == 0 {
+                anyhow::bail!(
+                    "unexpected eof reading {} at {}",
+                    self.fpath,
+                    self.buffers_pos[idx] + self.buffers[idx].len as u64
+                );
+            }
+            self.buffers[idx].len += n as usize;
+            if self.buffers[idx].len < self.buff_size {
+                // short read, read the rest of the buffer. O_DIRECT needs an aligned offset,
+                // so the unaligned part is read again.
+                self.buffers[idx].len -= self.buffers[idx].len % get_page_size();
+                self.push_read(idx)?;
+                continue;
+            }
             self.buffers_flag[idx] = BufferStatus::Ready4Process;
-            self.buffers[idx].len = cqe.result() as usize;
-            assert_eq!(self.buffers[idx].len, self.buff_size);
 
             if self.buffers_flag[buf_idx] == BufferStatus::Ready4Process {
                 return Ok(());
@@ -210,7 +699,13 @@ impl SequentialReader {
         if (self.file_pos_cursor + self.buff_size as u64) > self.end_pos {
             // last read
             let remaining_bytes = (self.end_pos - self.file_pos_cursor) as usize;
-            let f = std::fs::File::open(&self.fpath)?;
+            if self.tail_file.is_none() {
+                // the same file even if the path names another one by now
+                let file = fs::File::open(format!("/proc/self/fd/{}", self.file.as_raw_fd()))
+                    .with_context(|| format!("open {} failed", self.fpath))?;
+                self.tail_file = Some(file);
+            }
+            let f = self.tail_file.as_ref().unwrap();
             f.read_exact_at(
                 &mut self.buffers[buf_idx][..remaining_bytes],
                 self.file_pos_cursor,
@@ -225,24 +720,140 @@ impl SequentialReader {
 
         let buf_cap = self.buffers[buf_idx].cap();
         self.buffers[buf_idx].len = 0; // reset length before read
+        self.buffers_pos[buf_idx] = self.file_pos_cursor;
+        self.push_read(buf_idx)?;
+        self.file_pos_cursor += buf_cap as u64;
+        Ok(())
+    }
+
+    fn wait_chunk_ready(&mut self) -> anyhow::Result<NextBuf> {
+        self.start_reads()?;
+        let provided = self.provided.as_mut().expect("provided buffer mode");
+        let chunk = provided.next_chunk;
+        let chunk_pos = provided.chunk_start + chunk * self.buff_size as u64;
+        if chunk_pos >= self.end_pos {
+            return Ok(NextBuf::Eof);
+        }
+
+        loop {
+            if let Some(&bid) = self.provided.as_ref().and_then(|p| p.ready.get(&chunk)) {
+                self.data_location.buf_idx = bid;
+                return Ok(NextBuf::Ready(bid));
+            }
+
+            let Some(cqe) = self.next_completion()? else {
+                return Ok(NextBuf::Pending);
+            };
+            let provided = self.provided.as_mut().expect("provided buffer mode");
+            let chunk_pos = provided.chunk_start + cqe.user_data * self.buff_size as u64;
+            let n = cqe
+                .into_result()
+                .with_context(|| format!("read {} at {} failed", self.fpath, chunk_pos))?;
+            let bid = io_uring::cqueue::buffer_select(cqe.flags)
+                .context("completion without a selected buffer")? as usize;
+            provided.ready.insert(cqe.user_data, bid);
+
+            let expected = (self.end_pos - chunk_pos).min(self.buff_size as u64) as usize;
+            let mut filled = n as usize;
+            while filled < expected {
+                // short read, read the rest here. O_DIRECT needs an aligned offset,
+                // so the unaligned part is read again.
+                filled -= filled % get_page_size();
+                let buf = &mut self.buffers[bid];
+                let cap = buf.cap();
+                let n = self
+                    .file
+                    .read_at(&mut buf[filled..cap], chunk_pos + filled as u64)
+                    .with_context(|| {
+                        format!("read {} at {} failed", self.fpath, chunk_pos + filled as u64)
+                    })?;
+                if n == 0 {
+                    anyhow::bail!(
+                        "unexpected eof reading {} at {}",
+                        self.fpath,
+                        chunk_pos + filled as u64
+                    );
+                }
+                filled += n;
+            }
+            self.buffers[bid].len = expected;
+        }
+    }
+
+    /// read the next chunk into whatever buffer the kernel picks
+    fn submit_provided_read(&mut self) -> anyhow::Result<()> {
+        let provided = self.provided.as_mut().expect("provided buffer mode");
+        let chunk = provided.next_submit;
+        let chunk_pos = provided.chunk_start + chunk * self.buff_size as u64;
+        if chunk_pos >= self.end_pos {
+            return Ok(());
+        }
+        provided.next_submit += 1;
+
+        let sqe = io_uring::opcode::Read::new(
+            self.ring.file(),
+            std::ptr::null_mut(),
+            self.buff_size as u32,
+        )
+        .offset(chunk_pos)
+        .buf_group(provided.bgid)
+        .build()
+        .flags(io_uring::squeue::Flags::BUFFER_SELECT)
+        .user_data(chunk);
+
+        unsafe { self.ring.push(sqe) }
+    }
+
+    /// read the part of the buffer that is not filled yet
+    fn push_read(&mut self, buf_idx: usize) -> anyhow::Result<()> {
+        let buf = &mut self.buffers[buf_idx];
+        let filled = buf.len;
         let sqe = io_uring::opcode::ReadFixed::new(
-            io_uring::types::Fixed(0),
-            self.buffers[buf_idx].as_mut_ptr(),
-            buf_cap as u32,
-            buf_idx as u16,
+            self.ring.file(),
+            unsafe { buf.as_mut_ptr().add(filled) },
+            (buf.cap() - filled) as u32,
+            self.ring.buf_index(buf_idx),
         )
-        .offset(self.file_pos_cursor)
+        .offset(self.buffers_pos[buf_idx] + filled as u64)
         .build()
         .user_data(buf_idx as u64);
 
-        unsafe {
-            self.ring
-                .submission()
-                .push(&sqe)
-                .context("Failed to push submission queue entry")?;
+        unsafe { self.ring.push(sqe) }
+    }
+}
+
+/// the lines of a `SequentialReader`, see `SequentialReader::lines`
+pub struct Lines<'a> {
+    reader: &'a mut SequentialReader,
+    max_len: Option<usize>,
+}
+
+impl Lines<'_> {
+    /// fail with `ErrorKind::InvalidData` on a line longer than `max_len`
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// the next line, valid until the next call. `None` at the end, a last line without `\n`
+    /// is returned as it is
+    pub fn next_line(&mut self) -> anyhow::Result<Option<&[u8]>> {
+        // the `\r` of `\r\n` is allowed beyond `max_len`
+        let max_len = self.max_len.map(|n| n.saturating_add(1));
+        let Some(mut line) = self.reader.read_until(b'\n', max_len)? else {
+            return Ok(None);
+        };
+        if let Some(rest) = line.strip_suffix(b"\n") {
+            line = rest.strip_suffix(b"\r").unwrap_or(rest);
         }
-        self.pending_io += 1;
-        self.file_pos_cursor += buf_cap as u64;
-        Ok(())
+        if let Some(max_len) = self.max_len
+            && line.len() > max_len
+        {
+            return Err(anyhow::Error::new(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("line longer than {} bytes", max_len),
+            )));
+        }
+        Ok(Some(line))
     }
 }
@@ -1,8 +1,12 @@
 #![cfg(target_os = "linux")]
 use std::{
     fs::{self, OpenOptions},
-    io::{Seek, Write},
-    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    io::ErrorKind,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    time::{Duration, Instant},
 };
 
 use crate::{
@@ -10,20 +14,123 @@ use crate::{
     linux::utils::get_page_size,
 };
 use anyhow::Context;
-use io_uring::IoUring;
 
-use super::buffer::Buffer;
+#[cfg(any(test, feature = "fault-injection"))]
+use super::fault::FaultPlan;
+use super::{
+    atomic::{self, AtomicTarget},
+    buffer::{AllocOptions, Buffer},
+    buffer_pool::{BufferPool, stream_buffers},
+    io_context::{IoContext, StreamRing},
+    lock::{self, LockMode},
+};
+
+/// when a `SequentialWriter` makes the written data durable with fdatasync.
+/// every policy but `None` also syncs in `finish`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// only `sync` does
+    #[default]
+    None,
+    /// once in `finish`
+    OnFinish,
+    /// after every this many bytes
+    EveryBytes(u64),
+    /// after the first write at least this long after the last sync
+    EveryInterval(Duration),
+    /// every write is O_DSYNC (`RWF_DSYNC`)
+    PerWrite,
+}
+
+/// disk space a `SequentialWriter` reserves with fallocate before writing to it.
+/// `finish` cuts the file back to the written length.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Preallocation {
+    #[default]
+    None,
+    /// the file up to `expected_size` at once, when the writer is created
+    UpFront { expected_size: u64, keep_size: bool },
+    /// `chunk` bytes at a time, at least half a chunk ahead of the write position
+    Chunked { chunk: u64, keep_size: bool },
+}
+
+/// tag of the fdatasync requests, the buffers use their index
+const SYNC_TAG: u64 = (1 << 31) - 1;
+/// tag of the fallocate requests
+const ALLOC_TAG: u64 = SYNC_TAG - 1;
+/// tag of the writes of the unaligned tail
+const TAIL_TAG: u64 = SYNC_TAG - 2;
+
+/// how far `finish` got, `try_finish` goes on from there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FinishStage {
+    Writing,
+    Tail,
+    Synced,
+    Done,
+}
+
+/// buffer settings of a `SequentialWriter`
+#[derive(Clone)]
+pub struct WriterConfig {
+    pub buffer_size: usize,
+    pub num_buffer: usize,
+    /// borrow the buffers from this pool instead of allocating them, `buffer_size` must match the pool's
+    pub pool: Option<BufferPool>,
+    /// huge pages and mlock of the buffers, the pool's options apply to pooled buffers
+    pub alloc: AllocOptions,
+    /// cancel a write still running after this long, it then fails with `ErrorKind::TimedOut`
+    pub timeout: Option<Duration>,
+    pub durability: Durability,
+    /// reserve the disk space ahead of the writes, with `FALLOC_FL_KEEP_SIZE` if `keep_size`
+    pub preallocation: Preallocation,
+    /// take an exclusive `flock` on the file
+    pub lock: LockMode,
+}
+
+impl WriterConfig {
+    pub fn new(buffer_size: usize, num_buffer: usize) -> Self {
+        Self {
+            buffer_size,
+            num_buffer,
+            pool: None,
+            alloc: AllocOptions::default(),
+            timeout: None,
+            durability: Durability::None,
+            preallocation: Preallocation::None,
+            lock: LockMode::None,
+        }
+    }
+}
+
 pub struct SequentialWriter {
     #[allow(unused)]
     file: fs::File, // 不能删掉。要保证文件是打开的！
     fpath: String,
     buffer_size: usize,
-    ring: IoUring,
+    ring: StreamRing,
     buffers: Vec<Buffer>,
     buffers_flag: Vec<BufferStatus>,
+    buffers_pos: Vec<u64>,        // 每个 buffer 对应的文件位置
     data_location: BufferDataPos, // 即将要读取的 buffer 以及 offset
-    pending_io: usize,
     file_pos_cursor: u64,
+    finish_stage: FinishStage,
+    tail_file: Option<fs::File>, // the file without O_DIRECT, for the unaligned tail
+    tail_written: usize,         // bytes of the tail written so far
+    tail_pending: bool,          // a write of the tail is in flight, it reads the current buffer
+    tail_dirty: bool,            // the tail has data `push_tail` did not write yet
+    deadline: Option<Instant>,   // of the current `write_timeout` call
+    nonblocking: bool,           // inside `try_write`, `try_flush` or `try_finish`
+    durability: Durability,
+    unsynced: u64,      // bytes submitted since the last sync
+    last_sync: Instant, // when the last sync was submitted
+    sync_due: bool,     // a short write is resubmitted, it syncs again
+    syncs: usize,       // fdatasyncs in flight
+    preallocation: Preallocation,
+    allocated_end: u64,           // the file is preallocated up to here
+    initial_size: u64,            // `finish` never cuts the file below this
+    atomic: Option<AtomicTarget>, // of `create_atomic` until `commit`
+    locked: bool,
 }
 
 impl SequentialWriter {
@@ -33,13 +140,77 @@ impl SequentialWriter {
         start_pos: u64,
         buffer_size: usize,
         num_buffer: usize,
+    ) -> anyhow::Result<Self> {
+        Self::with_config(fpath, start_pos, WriterConfig::new(buffer_size, num_buffer))
+    }
+
+    pub fn with_config(fpath: &str, start_pos: u64, config: WriterConfig) -> anyhow::Result<Self> {
+        Self::new_in(None, fpath, start_pos, config)
+    }
+
+    /// write a new file that only shows up at `final_path` on `commit`, replacing the file there.
+    /// the data goes to an `O_TMPFILE` in the same directory, or a hidden temp file if the file system
+    /// has none. dropping the writer without `commit` discards the data.
+    pub fn create_atomic(
+        final_path: &str,
+        buffer_size: usize,
+        num_buffer: usize,
+    ) -> anyhow::Result<Self> {
+        Self::create_atomic_with_config(final_path, WriterConfig::new(buffer_size, num_buffer))
+    }
+
+    /// `config.lock` locks the final path until `commit` or drop, not the temp file. the lock is
+    /// taken on a hidden `.<name>.lock` file next to it, which is left in place for the next writer
+    pub fn create_atomic_with_config(
+        final_path: &str,
+        mut config: WriterConfig,
+    ) -> anyhow::Result<Self> {
+        let (file, target) = atomic::create(final_path, config.lock)?;
+        config.lock = LockMode::None;
+        let mut writer = Self::from_file(None, file, final_path, 0, config)?;
+        writer.atomic = Some(target);
+        Ok(writer)
+    }
+
+    /// `ctx`: the shared ring to submit to, a private ring is created if `None`
+    pub(crate) fn new_in(
+        ctx: Option<&IoContext>,
+        fpath: &str,
+        start_pos: u64,
+        config: WriterConfig,
     ) -> anyhow::Result<Self> {
         let file = OpenOptions::new()
             .write(true)
             .create(true)
             .custom_flags(libc::O_DIRECT)
             .open(fpath)
-            .unwrap();
+            .with_context(|| format!("open {} failed", fpath))?;
+        Self::from_file(ctx, file, fpath, start_pos, config)
+    }
+
+    /// `file` is opened for writing with O_DIRECT
+    fn from_file(
+        ctx: Option<&IoContext>,
+        file: fs::File,
+        fpath: &str,
+        start_pos: u64,
+        config: WriterConfig,
+    ) -> anyhow::Result<Self> {
+        let WriterConfig {
+            buffer_size,
+            num_buffer,
+            pool,
+            alloc,
+            timeout,
+            durability,
+            preallocation,
+            lock,
+        } = config;
+        lock::lock(&file, fpath, true, lock)?;
+        let initial_size = file
+            .metadata()
+            .with_context(|| format!("stat {} failed", fpath))?
+            .len();
 
         let page_size = get_page_size();
         assert_eq!(buffer_size % page_size, 0);
@@ -49,78 +220,180 @@ impl SequentialWriter {
             anyhow::bail!("get_page_size returned 0, which is invalid");
         }
 
-        let ring = IoUring::new(num_buffer as u32).unwrap();
-
-        let mut buffers: Vec<Buffer> = (0..num_buffer)
-            .map(|_| Buffer::new(buffer_size, page_size))
-            .collect();
-
-        let iovecs = buffers
-            .iter_mut()
-            .map(|buf| {
-                let buf_len = buf.cap();
-                libc::iovec {
-                    iov_base: buf.as_mut_ptr() as *mut _,
-                    iov_len: buf_len as usize,
-                }
-            })
-            .collect::<Vec<_>>();
+        let mut buffers = stream_buffers(buffer_size, num_buffer, pool.as_ref(), alloc)?;
 
         let offset = start_pos as usize % buffer_size;
         let readstart = start_pos - offset as u64;
 
-        let data_location = BufferDataPos {
-            buf_idx: 0,
-            offset: offset as usize,
-        };
+        let data_location = BufferDataPos { buf_idx: 0, offset };
 
         let buffers_flag = vec![BufferStatus::Ready4Process; num_buffer];
 
-        unsafe {
-            ring.submitter()
-                .register_buffers(iovecs.as_slice())
-                .expect("register buffers error");
-            ring.submitter()
-                .register_files(&[file.as_raw_fd()])
-                .expect("register file error");
-        }
+        // a request linked to its timeout takes two entries
+        let entries = if timeout.is_some() {
+            2 * num_buffer
+        } else {
+            num_buffer
+        };
+        let mut ring = match ctx {
+            Some(ctx) => ctx.open_stream(Some(file.as_raw_fd()), &mut buffers)?,
+            None => StreamRing::private(entries as u32, file.as_raw_fd(), &mut buffers)?,
+        };
+        ring.set_timeout(timeout);
 
-        Ok(Self {
+        let mut writer = Self {
             file,
             fpath: fpath.to_string(),
             buffer_size,
             ring,
             buffers,
             buffers_flag,
-            data_location: data_location,
-            pending_io: 0,
+            buffers_pos: vec![0; num_buffer],
+            data_location,
             file_pos_cursor: readstart,
-        })
+            finish_stage: FinishStage::Writing,
+            tail_file: None,
+            tail_written: 0,
+            tail_pending: false,
+            tail_dirty: offset > 0,
+            deadline: None,
+            nonblocking: false,
+            durability,
+            unsynced: 0,
+            last_sync: Instant::now(),
+            sync_due: false,
+            syncs: 0,
+            preallocation,
+            allocated_end: readstart,
+            initial_size,
+            atomic: None,
+            locked: lock != LockMode::None,
+        };
+        if let Preallocation::UpFront { expected_size, .. } = preallocation
+            && expected_size > readstart
+        {
+            writer.push_fallocate(expected_size - readstart)?;
+            writer.reap_all()?;
+        }
+        Ok(writer)
+    }
+
+    /// script faults for the requests this writer submits from now on
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn set_fault_plan(&mut self, plan: FaultPlan) {
+        self.ring.set_fault_plan(plan);
     }
 
     pub fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.write_some(data)?;
+        Ok(())
+    }
+
+    /// copy as much of `data` as fits in the buffers that are not being written, never waits for the disk.
+    /// returns the bytes taken, fails with `ErrorKind::WouldBlock` if no buffer is free,
+    /// `eventfd` tells when to try again.
+    pub fn try_write(&mut self, data: &[u8]) -> anyhow::Result<usize> {
+        self.nonblocking = true;
+        let res = self.write_some(data);
+        self.nonblocking = false;
+        match res {
+            Ok(0) if !data.is_empty() => Err(std::io::Error::from(ErrorKind::WouldBlock).into()),
+            res => res,
+        }
+    }
+
+    /// reap the finished writes, fails with `ErrorKind::WouldBlock` while some are still running.
+    /// the partly filled buffer is only written by `finish`, `try_flush_tail` and `sync`.
+    pub fn try_flush(&mut self) -> anyhow::Result<()> {
+        self.nonblocking = true;
+        let res = self.reap_all();
+        self.nonblocking = false;
+        if !res? {
+            return Err(std::io::Error::from(ErrorKind::WouldBlock).into());
+        }
+        Ok(())
+    }
+
+    /// `try_flush` that also writes the partly filled buffer like `sync`, without the fdatasync.
+    /// fails with `ErrorKind::WouldBlock` until all of it is written.
+    pub fn try_flush_tail(&mut self) -> anyhow::Result<()> {
+        self.nonblocking = true;
+        let res = self.flush_tail();
+        self.nonblocking = false;
+        if !res? {
+            return Err(std::io::Error::from(ErrorKind::WouldBlock).into());
+        }
+        Ok(())
+    }
+
+    /// `false` if `try_flush_tail` finds writes still running
+    fn flush_tail(&mut self) -> anyhow::Result<bool> {
+        if !self.reap_all()? {
+            return Ok(false);
+        }
+        if self.tail_dirty {
+            self.push_tail()?;
+        }
+        self.reap_all()
+    }
+
+    /// `false` if `try_flush` finds writes still running
+    fn reap_all(&mut self) -> anyhow::Result<bool> {
+        while self.ring.in_flight() > 0 {
+            if !self.reap_one()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// an eventfd that becomes readable when a write completes, `try_write` resets it.
+    /// with a shared `IoContext` it is the context's eventfd.
+    pub fn eventfd(&mut self) -> anyhow::Result<RawFd> {
+        self.ring.eventfd()
+    }
+
+    /// fdatasyncs and the other requests in flight
+    #[cfg(test)]
+    pub(crate) fn in_flight(&self) -> (usize, usize) {
+        (self.syncs, self.ring.in_flight() - self.syncs)
+    }
+
+    /// all of `data` unless called from `try_write`
+    fn write_some(&mut self, data: &[u8]) -> anyhow::Result<usize> {
         let record_len = data.len();
         let mut data_start = 0;
 
         while data_start < record_len {
+            while self.tail_pending {
+                if !self.reap_one()? {
+                    // only in `try_write`
+                    return Ok(data_start);
+                }
+            }
             let buf_idx = self.data_location.buf_idx;
             self.wait_buf_ready4write(buf_idx)?;
+            if self.buffers_flag[buf_idx] != BufferStatus::Ready4Process {
+                // only in `try_write`
+                return Ok(data_start);
+            }
 
             let expected_data_size = record_len - data_start;
 
             let (fill_size, current_buf_remaining) = {
                 let buf = &mut self.buffers[buf_idx];
 
-                let current_buf_remaining = (self.buffer_size - self.data_location.offset) as usize;
+                let current_buf_remaining = self.buffer_size - self.data_location.offset;
                 let fill_size = current_buf_remaining.min(expected_data_size);
 
-                (&mut buf[self.data_location.offset..self.data_location.offset + fill_size])
+                buf[self.data_location.offset..self.data_location.offset + fill_size]
                     .copy_from_slice(&data[data_start..data_start + fill_size]);
 
                 (fill_size, current_buf_remaining)
             };
 
             self.data_location.offset += fill_size;
+            self.tail_dirty = true;
             data_start += fill_size;
             if expected_data_size >= current_buf_remaining {
                 let next_buf_idx: usize = (buf_idx + 1) % self.buffers.len();
@@ -132,7 +405,16 @@ impl SequentialWriter {
             }
         }
 
-        Ok(())
+        Ok(record_len)
+    }
+
+    /// `write` that fails with `ErrorKind::TimedOut` if no buffer frees up within `timeout`.
+    /// the writes in flight are cancelled then, the writer can not be used after a timeout.
+    pub fn write_timeout(&mut self, data: &[u8], timeout: Duration) -> anyhow::Result<()> {
+        self.deadline = Some(Instant::now() + timeout);
+        let res = self.write(data);
+        self.deadline = None;
+        res
     }
 
     fn wait_buf_ready4write(&mut self, buf_idx: usize) -> anyhow::Result<()> {
@@ -140,21 +422,10 @@ impl SequentialWriter {
             return Ok(());
         }
 
-        while self.pending_io > 0 {
-            self.ring
-                .submit_and_wait(1)
-                .expect("Failed to submit and wait");
-            let cqe = self
-                .ring
-                .completion()
-                .next()
-                .expect("No completion event found");
-            self.pending_io -= 1;
-
-            let idx = cqe.user_data() as usize;
-            self.buffers_flag[idx] = BufferStatus::Ready4Process;
-            self.buffers[idx].len = cqe.result() as usize;
-            assert_eq!(self.buffers[idx].len, self.buffer_size);
+        while self.ring.in_flight() > 0 {
+            if !self.reap_one()? {
+                return Ok(());
+            }
 
             if self.buffers_flag[buf_idx] == BufferStatus::Ready4Process {
                 return Ok(());
@@ -164,56 +435,331 @@ impl SequentialWriter {
         anyhow::bail!("buffer {} is not ready for read", buf_idx);
     }
 
+    /// handle one write completion, resubmitting the rest of the buffer on a short write.
+    /// `false` if there is none yet in `try_write`.
+    fn reap_one(&mut self) -> anyhow::Result<bool> {
+        let cqe = if self.nonblocking {
+            match self.ring.try_wait()? {
+                Some(cqe) => cqe,
+                None => return Ok(false),
+            }
+        } else {
+            self.ring.wait_deadline(self.deadline)?
+        };
+        if cqe.user_data == ALLOC_TAG {
+            match cqe.into_result() {
+                // preallocation only helps, a file system without it is fine
+                Err(e) if e.raw_os_error() != Some(libc::EOPNOTSUPP) => {
+                    return Err(e).with_context(|| format!("fallocate {} failed", self.fpath));
+                }
+                _ => {}
+            }
+            return Ok(true);
+        }
+        if cqe.user_data == TAIL_TAG {
+            self.tail_pending = false;
+            let n = cqe.into_result().with_context(|| {
+                format!(
+                    "write tail of {} at {} failed",
+                    self.fpath,
+                    self.file_pos_cursor + self.tail_written as u64
+                )
+            })?;
+            if n == 0 {
+                anyhow::bail!("write tail of {} made no progress", self.fpath);
+            }
+            self.tail_written += n as usize;
+            if self.tail_written < self.data_location.offset {
+                self.push_tail_write()?;
+            }
+            return Ok(true);
+        }
+        if cqe.user_data == SYNC_TAG {
+            self.syncs -= 1;
+            match cqe.into_result() {
+                // the write it is linked to was short, the rest syncs again once it is written
+                Err(e) if e.raw_os_error() == Some(libc::ECANCELED) => {}
+                res => {
+                    res.with_context(|| format!("fdatasync {} failed", self.fpath))?;
+                }
+            }
+            return Ok(true);
+        }
+        let idx = cqe.user_data as usize;
+        let n = cqe.into_result().with_context(|| {
+            format!(
+                "write {} at {} failed",
+                self.fpath,
+                self.buffers_pos[idx] + self.buffers[idx].len as u64
+            )
+        })?;
+        if n == 0 {
+            anyhow::bail!(
+                "write {} at {} made no progress",
+                self.fpath,
+                self.buffers_pos[idx] + self.buffers[idx].len as u64
+            );
+        }
+        self.buffers[idx].len += n as usize;
+        if self.buffers[idx].len < self.buffer_size {
+            // short write. O_DIRECT needs an aligned offset, so the unaligned part is written again
+            self.buffers[idx].len -= self.buffers[idx].len % get_page_size();
+            // a sync issued since the first part may have missed the rest
+            self.sync_due = matches!(
+                self.durability,
+                Durability::EveryBytes(_) | Durability::EveryInterval(_)
+            );
+            self.push_write(idx)?;
+            return Ok(true);
+        }
+        self.buffers_flag[idx] = BufferStatus::Ready4Process;
+        Ok(true)
+    }
+
     fn submit_write_event(&mut self, buf_idx: usize) -> anyhow::Result<()> {
         let buf_cap = self.buffers[buf_idx].cap();
-        self.buffers[buf_idx].len = 0; // reset length before read
-        let sqe = io_uring::opcode::WriteFixed::new(
-            io_uring::types::Fixed(0),
-            self.buffers[buf_idx].as_mut_ptr(),
-            buf_cap as u32,
-            buf_idx as u16,
+        self.buffers[buf_idx].len = 0; // reset length before write, counts the bytes written
+        self.buffers_pos[buf_idx] = self.file_pos_cursor;
+        if let Preallocation::Chunked { chunk, .. } = self.preallocation {
+            while self.file_pos_cursor + (buf_cap as u64) + chunk / 2 > self.allocated_end {
+                self.push_fallocate(chunk.max(buf_cap as u64))?;
+            }
+        }
+        self.push_write(buf_idx)?;
+        self.file_pos_cursor += buf_cap as u64;
+        Ok(())
+    }
+
+    /// write the part of the buffer that is not written yet, followed by an fdatasync when the
+    /// durability policy asks for one. the fdatasync is linked to the write, it starts once that is
+    /// done and covers every write completed by then, the later writes go on meanwhile
+    fn push_write(&mut self, buf_idx: usize) -> anyhow::Result<()> {
+        let buf = &mut self.buffers[buf_idx];
+        let written = buf.len;
+        let len = buf.cap() - written;
+        let mut write = io_uring::opcode::WriteFixed::new(
+            self.ring.file(),
+            unsafe { buf.as_mut_ptr().add(written) },
+            len as u32,
+            self.ring.buf_index(buf_idx),
         )
-        .offset(self.file_pos_cursor)
+        .offset(self.buffers_pos[buf_idx] + written as u64);
+        if self.durability == Durability::PerWrite {
+            write = write.rw_flags(libc::RWF_DSYNC);
+        }
+        let sqe = write.build().user_data(buf_idx as u64);
+
+        self.unsynced += len as u64;
+        let sync = self.sync_due
+            || match self.durability {
+                Durability::EveryBytes(n) => self.unsynced >= n,
+                Durability::EveryInterval(interval) => self.last_sync.elapsed() >= interval,
+                _ => false,
+            };
+        if !sync {
+            return unsafe { self.ring.push(sqe) };
+        }
+        self.mark_synced();
+        self.syncs += 1;
+        unsafe { self.ring.push_link(&[sqe, self.sync_sqe()]) }
+    }
+
+    /// reserve `len` bytes from `allocated_end` on
+    fn push_fallocate(&mut self, len: u64) -> anyhow::Result<()> {
+        let mode = match self.preallocation {
+            Preallocation::UpFront {
+                keep_size: true, ..
+            }
+            | Preallocation::Chunked {
+                keep_size: true, ..
+            } => libc::FALLOC_FL_KEEP_SIZE,
+            _ => 0,
+        };
+        let sqe = io_uring::opcode::Fallocate::new(self.ring.file(), len)
+            .offset(self.allocated_end)
+            .mode(mode)
+            .build()
+            .user_data(ALLOC_TAG);
+        self.allocated_end += len;
+        // no memory is shared with the kernel
+        unsafe { self.ring.push(sqe) }
+    }
+
+    fn sync_sqe(&self) -> io_uring::squeue::Entry {
+        io_uring::opcode::Fsync::new(self.ring.file())
+            .flags(io_uring::types::FsyncFlags::DATASYNC)
+            .build()
+            .user_data(SYNC_TAG)
+    }
+
+    fn mark_synced(&mut self) {
+        self.sync_due = false;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+    }
+
+    /// return once everything given to `write` so far is durable. the partly filled buffer is
+    /// written as well, it stays in place for the data that follows.
+    pub fn sync(&mut self) -> anyhow::Result<()> {
+        self.reap_all()?;
+        self.push_tail()?;
+        self.sync_now()
+    }
+
+    /// fdatasync once the writes in flight are done
+    fn sync_now(&mut self) -> anyhow::Result<()> {
+        self.reap_all()?;
+        self.push_sync()?;
+        self.reap_all()?;
+        Ok(())
+    }
+
+    fn push_sync(&mut self) -> anyhow::Result<()> {
+        self.mark_synced();
+        self.syncs += 1;
+        // no memory is shared with the kernel
+        unsafe { self.ring.push(self.sync_sqe()) }
+    }
+
+    /// write the filled part of the current buffer without handing the buffer out. it goes
+    /// through the file without O_DIRECT, so no padding overwrites old data behind it.
+    fn push_tail(&mut self) -> anyhow::Result<()> {
+        self.tail_dirty = false;
+        if self.data_location.offset == 0 {
+            return Ok(());
+        }
+        if self.tail_file.is_none() {
+            // the path may not name the file (`create_atomic`)
+            let file = OpenOptions::new()
+                .write(true)
+                .open(format!("/proc/self/fd/{}", self.file.as_raw_fd()))
+                .with_context(|| format!("open {} failed", self.fpath))?;
+            self.tail_file = Some(file);
+        }
+        self.tail_written = 0;
+        self.push_tail_write()
+    }
+
+    /// write the part of the tail that is not written yet
+    fn push_tail_write(&mut self) -> anyhow::Result<()> {
+        let fd = self.tail_file.as_ref().unwrap().as_raw_fd();
+        let buf = &self.buffers[self.data_location.buf_idx];
+        let len = self.data_location.offset - self.tail_written;
+        let sqe = io_uring::opcode::Write::new(
+            io_uring::types::Fd(fd),
+            unsafe { buf.as_ptr().add(self.tail_written) },
+            len as u32,
+        )
+        .offset(self.file_pos_cursor + self.tail_written as u64)
         .build()
-        .user_data(buf_idx as u64);
+        .user_data(TAIL_TAG);
+        self.tail_pending = true;
+        // the buffer and `tail_file` live as long as the writer, whose drop waits for the ring
+        unsafe { self.ring.push(sqe) }
+    }
 
-        unsafe {
-            self.ring
-                .submission()
-                .push(&sqe)
-                .context("Failed to push submission queue entry")?;
+    /// wait for all submitted buffers and write the unaligned tail.
+    /// the writer must not be used after this returns.
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        self.finish_some()?;
+        Ok(())
+    }
+
+    /// `finish` that fails with `ErrorKind::WouldBlock` instead of waiting for the disk,
+    /// `eventfd` tells when to call it again
+    pub fn try_finish(&mut self) -> anyhow::Result<()> {
+        self.nonblocking = true;
+        let res = self.finish_some();
+        self.nonblocking = false;
+        if !res? {
+            return Err(std::io::Error::from(ErrorKind::WouldBlock).into());
+        }
+        Ok(())
+    }
+
+    /// `false` if `try_finish` has to wait for the disk. a failed finish is not tried again.
+    fn finish_some(&mut self) -> anyhow::Result<bool> {
+        let res = self.finish_stages();
+        if res.is_err() {
+            self.finish_stage = FinishStage::Done;
+        }
+        res
+    }
+
+    fn finish_stages(&mut self) -> anyhow::Result<bool> {
+        loop {
+            if self.finish_stage == FinishStage::Done {
+                return Ok(true);
+            }
+            if !self.reap_all()? {
+                return Ok(false);
+            }
+            match self.finish_stage {
+                FinishStage::Writing => {
+                    if self.tail_dirty {
+                        self.push_tail()?;
+                    }
+                    self.finish_stage = FinishStage::Tail;
+                }
+                FinishStage::Tail => {
+                    if self.preallocation != Preallocation::None {
+                        // drop the space reserved past the end
+                        let end = self.file_pos_cursor + self.data_location.offset as u64;
+                        self.file
+                            .set_len(self.initial_size.max(end))
+                            .with_context(|| format!("truncate {} failed", self.fpath))?;
+                    }
+                    if self.durability != Durability::None {
+                        self.push_sync()?;
+                    }
+                    self.finish_stage = FinishStage::Synced;
+                }
+                FinishStage::Synced => {
+                    if self.locked {
+                        self.unlock()?;
+                    }
+                    self.finish_stage = FinishStage::Done;
+                }
+                FinishStage::Done => unreachable!(),
+            }
+        }
+    }
+
+    fn unlock(&mut self) -> anyhow::Result<()> {
+        self.locked = false;
+        lock::unlock(&self.file, &self.fpath)
+    }
+
+    /// `finish`, fsync the file, move it to the final path of `create_atomic` and fsync the directory.
+    /// readers see either the old file or all of the new one.
+    pub fn commit(&mut self) -> anyhow::Result<()> {
+        if self.atomic.is_none() {
+            anyhow::bail!("{} was not created by create_atomic", self.fpath);
+        }
+        self.finish()?;
+        if self.durability == Durability::None {
+            self.sync_now()?;
+        }
+        // dropping the target releases the lock of the final path once the file is in place
+        if let Some(mut target) = self.atomic.take() {
+            let res = target.publish(&self.file);
+            if res.is_err() {
+                target.discard();
+            }
+            res?;
         }
-        self.pending_io += 1;
-        self.file_pos_cursor += buf_cap as u64;
         Ok(())
     }
 }
 
+/// finishes the writer, an error is discarded. call `finish` to see it.
 impl Drop for SequentialWriter {
     fn drop(&mut self) {
-        while self.pending_io > 0 {
-            self.ring
-                .submit_and_wait(1)
-                .expect("Failed to submit and wait");
-            let _cqe = self
-                .ring
-                .completion()
-                .next()
-                .expect("No completion event found");
-            self.pending_io -= 1;
-        }
-
-        if self.data_location.offset > 0 {
-            let mut f = std::fs::OpenOptions::new()
-                .read(true) // 可读
-                .write(true) // 可写
-                .create(true) // 不存在则创建
-                .open(&self.fpath)
-                .unwrap();
-            f.seek(std::io::SeekFrom::Start(self.file_pos_cursor as u64))
-                .unwrap();
-            f.write_all(&self.buffers[self.data_location.buf_idx][..self.data_location.offset])
-                .unwrap();
+        if let Some(mut target) = self.atomic.take() {
+            // not committed
+            target.discard();
+            return;
         }
+        let _ = self.finish();
     }
 }
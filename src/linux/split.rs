@@ -0,0 +1,153 @@
+#![cfg(target_os = "linux")]
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    ops::Range,
+    os::unix::fs::FileExt,
+};
+
+use anyhow::Context;
+
+use super::frame::FrameFormat;
+
+/// how the records of a file are separated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Records {
+    /// every record ends with this byte, e.g. `b'\n'`
+    Delimiter(u8),
+    /// length-prefixed frames, they are walked from the start of the file
+    Frames(FrameFormat),
+}
+
+impl From<FrameFormat> for Records {
+    fn from(format: FrameFormat) -> Self {
+        Self::Frames(format)
+    }
+}
+
+/// bytes read per probe for a delimiter
+const PROBE_SIZE: usize = 64 * 1024;
+
+/// split a file into at most `n` ranges of about the same size that start and end on record
+/// boundaries, to give each to a `SequentialReader` as `start_pos` and `end_pos`.
+/// the ranges are in file order, cover the whole file and are not empty.
+pub fn split_ranges(
+    fpath: &str,
+    n: usize,
+    records: impl Into<Records>,
+) -> anyhow::Result<Vec<Range<u64>>> {
+    if n == 0 {
+        anyhow::bail!("n must not be 0");
+    }
+    let file = fs::File::open(fpath).with_context(|| format!("open {} failed", fpath))?;
+    let size = file
+        .metadata()
+        .with_context(|| format!("stat {} failed", fpath))?
+        .len();
+    let targets = (1..n as u64).map(|i| size / n as u64 * i + size % n as u64 * i / n as u64);
+    let mut bounds = vec![0];
+    match records.into() {
+        Records::Delimiter(delim) => {
+            for target in targets {
+                let prev = *bounds.last().unwrap();
+                bounds.push(next_delimited(&file, fpath, delim, target.max(prev), size)?);
+            }
+        }
+        Records::Frames(format) => {
+            let mut walker = FrameWalker {
+                reader: BufReader::with_capacity(PROBE_SIZE, &file),
+                fpath,
+                format,
+                pos: 0,
+                size,
+            };
+            for target in targets {
+                bounds.push(walker.skip_to(target)?);
+            }
+        }
+    }
+    bounds.push(size);
+    bounds.dedup();
+    Ok(bounds.windows(2).map(|w| w[0]..w[1]).collect())
+}
+
+/// the first record start at or after `pos`
+fn next_delimited(
+    file: &fs::File,
+    fpath: &str,
+    delim: u8,
+    pos: u64,
+    size: u64,
+) -> anyhow::Result<u64> {
+    if pos == 0 || pos >= size {
+        return Ok(pos.min(size));
+    }
+    // a record starts at `pos` if the byte before it ends one
+    let mut at = pos - 1;
+    let mut buf = vec![0u8; PROBE_SIZE];
+    while at < size {
+        let len = PROBE_SIZE.min((size - at) as usize);
+        file.read_exact_at(&mut buf[..len], at)
+            .with_context(|| format!("read {} at {} failed", fpath, at))?;
+        if let Some(i) = buf[..len].iter().position(|&b| b == delim) {
+            return Ok(at + i as u64 + 1);
+        }
+        at += len as u64;
+    }
+    Ok(size)
+}
+
+/// reads the frame headers from the start of the file, skipping the payloads
+struct FrameWalker<'a> {
+    reader: BufReader<&'a fs::File>,
+    fpath: &'a str,
+    format: FrameFormat,
+    pos: u64, // start of the next frame
+    size: u64,
+}
+
+impl FrameWalker<'_> {
+    /// the first frame start at or after `target`
+    fn skip_to(&mut self, target: u64) -> anyhow::Result<u64> {
+        while self.pos < target && self.pos < self.size {
+            let (header_len, payload_len) = self.read_header()?;
+            let frame_len = self.format.frame_len(header_len, payload_len)?;
+            let end = self.pos.checked_add(frame_len);
+            if end.is_none_or(|end| end > self.size) {
+                anyhow::bail!(
+                    "frame of {} bytes at {} of {} goes past the end",
+                    frame_len,
+                    self.pos,
+                    self.fpath
+                );
+            }
+            self.reader
+                .seek_relative((frame_len - header_len as u64) as i64)
+                .with_context(|| format!("seek {} failed", self.fpath))?;
+            self.pos += frame_len;
+        }
+        Ok(self.pos)
+    }
+
+    /// parse the length prefix at `pos` and consume it
+    fn read_header(&mut self) -> anyhow::Result<(usize, u64)> {
+        let mut header = Vec::with_capacity(self.format.max_header_len());
+        loop {
+            let buf = self
+                .reader
+                .fill_buf()
+                .with_context(|| format!("read {} at {} failed", self.fpath, self.pos))?;
+            if buf.is_empty() {
+                anyhow::bail!("truncated frame header at {} of {}", self.pos, self.fpath);
+            }
+            let take = buf.len().min(self.format.max_header_len() - header.len());
+            let before = header.len();
+            header.extend_from_slice(&buf[..take]);
+            if let Some((header_len, payload_len)) = self.format.decode_header(&header)? {
+                self.reader.consume(header_len - before);
+                return Ok((header_len, payload_len));
+            }
+            self.reader.consume(take);
+        }
+    }
+}
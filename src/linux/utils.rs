@@ -1,6 +1,10 @@
-/// convert Rust &str path to wide null-terminated Vec<u16>
-
 pub const fn get_page_size() -> usize {
     // unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
     4096
 }
+
+/// the error of a `try_*` call that has to wait for the disk
+pub(crate) fn would_block(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::WouldBlock)
+}
@@ -3,13 +3,17 @@
 
 use crate::windows::utils::str_to_wide;
 use std::ffi::c_void;
+use std::time::Duration;
 use windows_sys::Win32::Foundation::{CloseHandle, GENERIC_WRITE, INVALID_HANDLE_VALUE};
-use windows_sys::Win32::Foundation::{GENERIC_READ, GetLastError};
+use windows_sys::Win32::Foundation::{GENERIC_READ, GetLastError, WAIT_TIMEOUT};
 use windows_sys::Win32::Storage::FileSystem::{
     CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_FLAG_OVERLAPPED, FILE_FLAG_SEQUENTIAL_SCAN,
     FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_ALWAYS, OPEN_EXISTING,
 };
-use windows_sys::Win32::System::IO::CreateIoCompletionPort;
+use windows_sys::Win32::System::IO::{
+    CancelIoEx, CreateIoCompletionPort, GetQueuedCompletionStatus, OVERLAPPED,
+};
+use windows_sys::Win32::System::Threading::INFINITE;
 
 #[derive(Debug, Clone, Copy)]
 pub enum FileMode {
@@ -52,6 +56,11 @@ impl FileHandle {
 
         Ok(Self { handle })
     }
+
+    /// cancel all the pending requests on the file, they complete with `ERROR_OPERATION_ABORTED`
+    pub fn cancel(&self) {
+        unsafe { CancelIoEx(self.handle, std::ptr::null()) };
+    }
 }
 
 impl Drop for FileHandle {
@@ -87,6 +96,44 @@ impl IocpHandle {
         self.handle = new_handle;
         Ok(())
     }
+
+    /// wait for one completion, forever if `timeout` is `None`.
+    /// returns the result of the request, the bytes transferred or its error, and its `OVERLAPPED`.
+    /// fails if no completion was dequeued, with `ErrorKind::TimedOut` after `timeout`.
+    pub fn wait(
+        &self,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<(std::io::Result<u32>, *mut OVERLAPPED)> {
+        let millis = match timeout {
+            Some(timeout) => timeout.as_millis().min((INFINITE - 1) as u128) as u32,
+            None => INFINITE,
+        };
+        let mut bytes_transferred: u32 = 0;
+        let mut completion_key: usize = 0;
+        let mut pov: *mut OVERLAPPED = std::ptr::null_mut();
+        let ok = unsafe {
+            GetQueuedCompletionStatus(
+                self.handle,
+                &mut bytes_transferred as *mut u32,
+                &mut completion_key as *mut usize,
+                &mut pov as *mut *mut OVERLAPPED,
+                millis,
+            )
+        };
+        if ok != 0 {
+            return Ok((Ok(bytes_transferred), pov));
+        }
+
+        let err = unsafe { GetLastError() };
+        if pov.is_null() {
+            if err == WAIT_TIMEOUT {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            return Err(std::io::Error::from_raw_os_error(err as i32));
+        }
+        // the request failed, its completion was dequeued all the same
+        Ok((Err(std::io::Error::from_raw_os_error(err as i32)), pov))
+    }
 }
 
 impl Drop for IocpHandle {
@@ -1,10 +1,12 @@
 #![cfg(windows)]
 #![allow(non_snake_case)]
 use std::io::{Read, Seek};
+use std::time::Duration;
+
+use anyhow::Context;
 
 use windows_sys::Win32::Storage::FileSystem::ReadFile;
-use windows_sys::Win32::System::IO::{GetQueuedCompletionStatus, OVERLAPPED};
-use windows_sys::Win32::System::Threading::INFINITE;
+use windows_sys::Win32::System::IO::OVERLAPPED;
 
 use super::buffer::ReaderBuffer;
 use crate::buffer_aux::{BufferDataPos, BufferStatus};
@@ -22,6 +24,7 @@ pub struct SequentialReader {
     end_pos: u64,
     init_flag: bool,
     pendding: usize,
+    timeout: Option<Duration>,
 
     iocp: IocpHandle,
 }
@@ -73,10 +76,25 @@ impl SequentialReader {
             end_pos: end_pos,
             init_flag: false,
             pendding: 0,
+            timeout: None,
             iocp,
         })
     }
 
+    /// fail a read that does not complete within `timeout` with `ErrorKind::TimedOut`,
+    /// the pending reads are cancelled then and the reader can not be used anymore
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// `read2buf` waiting at most `timeout` for each completion
+    pub fn read2buf_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> anyhow::Result<usize> {
+        let saved = self.timeout.replace(timeout);
+        let res = self.read2buf(buf);
+        self.timeout = saved;
+        res
+    }
+
     pub fn read2buf(&mut self, buf: &mut [u8]) -> anyhow::Result<usize> {
         let req_len = buf.len();
         let mut remaining_bytes = req_len;
@@ -135,22 +153,17 @@ impl SequentialReader {
         }
 
         while self.pendding > 0 {
-            let mut bytes_transferred: u32 = 0;
-            let mut completion_key: usize = 0;
-            let mut pov: *mut OVERLAPPED = std::ptr::null_mut();
-            let _ok = unsafe {
-                GetQueuedCompletionStatus(
-                    self.iocp.handle,
-                    &mut bytes_transferred as *mut u32,
-                    &mut completion_key as *mut usize,
-                    &mut pov as *mut *mut OVERLAPPED,
-                    INFINITE,
-                )
+            let (res, pov) = match self.iocp.wait(self.timeout) {
+                Ok(done) => done,
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::TimedOut {
+                        self.handle.cancel();
+                    }
+                    return Err(e).with_context(|| format!("read {} failed", self.fpath));
+                }
             };
-
-            if pov == std::ptr::null_mut() {
-                panic!("pov is null");
-            }
+            self.pendding -= 1;
+            let bytes_transferred = res.with_context(|| format!("read {} failed", self.fpath))?;
 
             let task: *mut ReaderBuffer = pov as *mut ReaderBuffer;
             unsafe {
@@ -160,7 +173,6 @@ impl SequentialReader {
             assert_eq!(bytes_transferred, self.buffer_size as u32);
             let idx = unsafe { (*task).idx };
             self.buffers_status[idx] = BufferStatus::Ready4Process;
-            self.pendding -= 1;
             if self.buffers_status[self.data_pos.buf_idx] == BufferStatus::Ready4Process {
                 return Ok(());
             }
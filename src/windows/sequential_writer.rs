@@ -1,9 +1,11 @@
 #![cfg(windows)]
 #![allow(non_snake_case)]
 use std::io::{Seek, Write};
+use std::time::Duration;
+
+use anyhow::Context;
 use windows_sys::Win32::Storage::FileSystem::WriteFile;
-use windows_sys::Win32::System::IO::{GetQueuedCompletionStatus, OVERLAPPED};
-use windows_sys::Win32::System::Threading::INFINITE;
+use windows_sys::Win32::System::IO::OVERLAPPED;
 
 use super::buffer::ReaderBuffer;
 use crate::buffer_aux::{BufferDataPos, BufferStatus};
@@ -18,6 +20,7 @@ pub struct SequentialWriter {
     data_pos: BufferDataPos,
     file_pos_cursor: u64,
     pendding: usize,
+    timeout: Option<Duration>,
 
     iocp: IocpHandle,
 }
@@ -58,10 +61,25 @@ impl SequentialWriter {
             data_pos: data_pose,
             file_pos_cursor: file_pos_cursor,
             pendding: 0,
+            timeout: None,
             iocp,
         })
     }
 
+    /// fail a write that does not complete within `timeout` with `ErrorKind::TimedOut`,
+    /// the pending writes are cancelled then and the writer can not be used anymore
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// `write` waiting at most `timeout` for each completion
+    pub fn write_timeout(&mut self, data: &[u8], timeout: Duration) -> anyhow::Result<()> {
+        let saved = self.timeout.replace(timeout);
+        let res = self.write(data);
+        self.timeout = saved;
+        res
+    }
+
     pub fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
         let req_len = data.len();
         let mut remaining_bytes = req_len;
@@ -105,22 +123,17 @@ impl SequentialWriter {
         }
 
         while self.pendding > 0 {
-            let mut bytes_transferred: u32 = 0;
-            let mut completion_key: usize = 0;
-            let mut pov: *mut OVERLAPPED = std::ptr::null_mut();
-            let _ok = unsafe {
-                GetQueuedCompletionStatus(
-                    self.iocp.handle,
-                    &mut bytes_transferred as *mut u32,
-                    &mut completion_key as *mut usize,
-                    &mut pov as *mut *mut OVERLAPPED,
-                    INFINITE,
-                )
+            let (res, pov) = match self.iocp.wait(self.timeout) {
+                Ok(done) => done,
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::TimedOut {
+                        self.handle.cancel();
+                    }
+                    return Err(e).with_context(|| format!("write {} failed", self.fpath));
+                }
             };
-
-            if pov == std::ptr::null_mut() {
-                panic!("pov is null");
-            }
+            self.pendding -= 1;
+            let bytes_transferred = res.with_context(|| format!("write {} failed", self.fpath))?;
 
             let task: *mut ReaderBuffer = pov as *mut ReaderBuffer;
             unsafe {
@@ -130,7 +143,6 @@ impl SequentialWriter {
             assert_eq!(bytes_transferred, self.buffer_size as u32);
             let idx = unsafe { (*task).idx };
             self.buffers_status[idx] = BufferStatus::Ready4Process;
-            self.pendding -= 1;
 
             // println!("wait_inner_buf_ready:{}", self.pendding);
 
@@ -196,29 +208,17 @@ impl SequentialWriter {
 impl Drop for SequentialWriter {
     fn drop(&mut self) {
         while self.pendding > 0 {
-            let mut bytes_transferred: u32 = 0;
-            let mut completion_key: usize = 0;
-            let mut pov: *mut OVERLAPPED = std::ptr::null_mut();
-            let _ok = unsafe {
-                GetQueuedCompletionStatus(
-                    self.iocp.handle,
-                    &mut bytes_transferred as *mut u32,
-                    &mut completion_key as *mut usize,
-                    &mut pov as *mut *mut OVERLAPPED,
-                    INFINITE,
-                )
+            // requests cancelled by a timeout complete too, wait for them all
+            // nothing completes on a broken port anymore, a failed write is lost with the writer
+            let Ok((res, pov)) = self.iocp.wait(None) else {
+                return;
             };
 
-            if pov == std::ptr::null_mut() {
-                panic!("pov is null");
-            }
-
             let task: *mut ReaderBuffer = pov as *mut ReaderBuffer;
             unsafe {
-                (*task).len = bytes_transferred as usize;
+                (*task).len = res.unwrap_or(0) as usize;
             }
 
-            assert_eq!(bytes_transferred, self.buffer_size as u32);
             let idx = unsafe { (*task).idx };
             self.buffers_status[idx] = BufferStatus::Ready4Process;
             self.pendding -= 1;